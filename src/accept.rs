@@ -0,0 +1,151 @@
+use std::io;
+use std::net::IpAddr;
+
+// A range of addresses written like 192.0.2.0/24 or 2001:db8::/32, where a
+// plain address is a range of just itself
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Network {
+    addr: IpAddr,
+    prefix: u32,
+}
+
+impl Network {
+    fn parse(s: &str) -> Option<Network> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr.parse::<IpAddr>().ok()?, Some(prefix.parse::<u32>().ok()?)),
+            None => (s.parse::<IpAddr>().ok()?, None),
+        };
+        let bits = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+
+        match prefix {
+            Some(prefix) if prefix > bits => None,
+            prefix => Some(Network { addr, prefix: prefix.unwrap_or(bits) }),
+        }
+    }
+
+    fn contains(self, addr: IpAddr) -> bool {
+        // The bits past the prefix are shifted out, so 192.0.2.1/24 is the
+        // same range as 192.0.2.0/24
+        match (self.addr, addr) {
+            (IpAddr::V4(network), IpAddr::V4(addr)) => {
+                let ignored = 32 - self.prefix;
+                u32::from(network).checked_shr(ignored).unwrap_or(0) == u32::from(addr).checked_shr(ignored).unwrap_or(0)
+            },
+            (IpAddr::V6(network), IpAddr::V6(addr)) => {
+                let ignored = 128 - self.prefix;
+                u128::from(network).checked_shr(ignored).unwrap_or(0) == u128::from(addr).checked_shr(ignored).unwrap_or(0)
+            },
+            _ => false,
+        }
+    }
+}
+
+// The sources besides the target whose echo replies count, for targets like
+// anycast services that answer from other addresses
+#[derive(Clone, Debug, Default)]
+pub struct AcceptFrom {
+    networks: Vec<Network>,
+}
+
+impl AcceptFrom {
+    // Parses a comma-separated list of addresses and ranges
+    pub fn parse(s: &str) -> io::Result<AcceptFrom> {
+        match s.split(',').map(|n| Network::parse(n.trim())).collect::<Option<Vec<_>>>() {
+            Some(networks) => Ok(AcceptFrom { networks }),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The value for the 'accept_from' flag must be a comma-separated list of addresses or ranges, like 192.0.2.1,198.51.100.0/24.",
+            )),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.networks.is_empty()
+    }
+
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        self.networks.iter().any(|n| n.contains(addr))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_addresses_and_ranges() {
+        assert_eq!(Network::parse("192.0.2.1"), Some(Network { addr: addr("192.0.2.1"), prefix: 32 }));
+        assert_eq!(Network::parse("192.0.2.0/24"), Some(Network { addr: addr("192.0.2.0"), prefix: 24 }));
+        assert_eq!(Network::parse("2001:db8::1"), Some(Network { addr: addr("2001:db8::1"), prefix: 128 }));
+        assert_eq!(Network::parse("2001:db8::/32"), Some(Network { addr: addr("2001:db8::"), prefix: 32 }));
+
+        for s in &["", "/24", "192.0.2.0/", "192.0.2.0/33", "2001:db8::/129", "192.0.2.0/-1", "192.0.2", "gw", "192.0.2.0/24/8"] {
+            assert_eq!(Network::parse(s), None, "'{}' was accepted", s);
+        }
+    }
+
+    #[test]
+    fn parses_lists() {
+        let accept = AcceptFrom::parse("192.0.2.1, 198.51.100.0/24,2001:db8::/32").unwrap();
+        assert_eq!(accept.networks.len(), 3);
+
+        for s in &["", "192.0.2.1,", "192.0.2.1,,192.0.2.2", "192.0.2.1 192.0.2.2"] {
+            assert!(AcceptFrom::parse(s).is_err(), "'{}' was accepted", s);
+        }
+    }
+
+    #[test]
+    fn matches_v4_ranges() {
+        let network = Network::parse("198.51.100.0/24").unwrap();
+        assert!(network.contains(addr("198.51.100.0")));
+        assert!(network.contains(addr("198.51.100.255")));
+        assert!(!network.contains(addr("198.51.101.0")));
+        assert!(!network.contains(addr("198.51.99.255")));
+
+        // The host bits don't matter
+        assert!(Network::parse("198.51.100.77/24").unwrap().contains(addr("198.51.100.1")));
+
+        let host = Network::parse("192.0.2.1").unwrap();
+        assert!(host.contains(addr("192.0.2.1")));
+        assert!(!host.contains(addr("192.0.2.2")));
+
+        let everything = Network::parse("0.0.0.0/0").unwrap();
+        assert!(everything.contains(addr("255.255.255.255")));
+        assert!(everything.contains(addr("0.0.0.0")));
+    }
+
+    #[test]
+    fn matches_v6_ranges() {
+        let network = Network::parse("2001:db8::/32").unwrap();
+        assert!(network.contains(addr("2001:db8::1")));
+        assert!(network.contains(addr("2001:db8:ffff:ffff:ffff:ffff:ffff:ffff")));
+        assert!(!network.contains(addr("2001:db9::")));
+
+        let network = Network::parse("2001:db8::/127").unwrap();
+        assert!(network.contains(addr("2001:db8::1")));
+        assert!(!network.contains(addr("2001:db8::2")));
+
+        assert!(Network::parse("::/0").unwrap().contains(addr("ffff::")));
+    }
+
+    #[test]
+    fn keeps_the_families_apart() {
+        assert!(!Network::parse("0.0.0.0/0").unwrap().contains(addr("::1")));
+        assert!(!Network::parse("::/0").unwrap().contains(addr("127.0.0.1")));
+        // Not even the v4-mapped form
+        assert!(!Network::parse("192.0.2.1").unwrap().contains(addr("::ffff:192.0.2.1")));
+
+        let accept = AcceptFrom::parse("192.0.2.0/24,2001:db8::/32").unwrap();
+        assert!(accept.contains(addr("192.0.2.9")));
+        assert!(accept.contains(addr("2001:db8::9")));
+        assert!(!accept.contains(addr("198.51.100.1")));
+        assert!(!AcceptFrom::default().contains(addr("192.0.2.9")));
+    }
+}
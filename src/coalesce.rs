@@ -0,0 +1,143 @@
+use std::time::{Duration, Instant};
+
+use crate::output::decimal;
+
+pub const DEFAULT_COALESCE_AFTER: u64 = 3;
+pub const DEFAULT_COALESCE_INTERVAL: Duration = Duration::from_secs(10);
+
+pub enum Action {
+    // Print the timeout's own line as usual
+    Verbatim,
+    // Replace the streak's line in place, which only works on a terminal
+    Update(String),
+    // Print a new line for the streak
+    Print(String),
+    // Print nothing for this timeout
+    Hide,
+}
+
+// Collapses long runs of timeouts into a single line. The first `after`
+// timeouts of a streak are printed as usual, then the streak's line is
+// updated in place on a terminal, or printed again every `interval` otherwise.
+pub struct Coalescer {
+    after: u64,
+    interval: Duration,
+    tty: bool,
+    last_printed: Option<Instant>,
+    // Whether any timeouts happened since the streak's line was last printed
+    unprinted: bool,
+    summary: String,
+}
+
+fn summary(consecutive: u64, streak: Duration) -> String {
+    format!("... {} consecutive timeouts (last {} s) ...", consecutive, decimal(streak.as_secs_f64(), 1))
+}
+
+impl Coalescer {
+    pub fn new(after: u64, interval: Duration, tty: bool) -> Coalescer {
+        Coalescer {
+            after,
+            interval,
+            tty,
+            last_printed: None,
+            unprinted: false,
+            summary: String::new(),
+        }
+    }
+
+    pub fn timeout(&mut self, consecutive: u64, streak: Duration, now: Instant) -> Action {
+        if consecutive <= self.after {
+            return Action::Verbatim;
+        }
+
+        self.summary = summary(consecutive, streak);
+
+        if self.tty {
+            return Action::Update(self.summary.clone());
+        }
+
+        let due = self.last_printed
+            .map(|t| now.duration_since(t) >= self.interval)
+            .unwrap_or(true);
+        if due {
+            self.last_printed = Some(now);
+            self.unprinted = false;
+            Action::Print(self.summary.clone())
+        } else {
+            self.unprinted = true;
+            Action::Hide
+        }
+    }
+
+    // Ends the streak, when a reply arrives or the run ends. Returns the
+    // streak's final line if the last few timeouts weren't shown.
+    pub fn end_streak(&mut self) -> Option<String> {
+        self.last_printed = None;
+
+        if self.unprinted {
+            self.unprinted = false;
+            Some(self.summary.clone())
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // What the coalescer does for a streak of `timeouts` timeouts, one every
+    // `spacing`, rendered like the output would be
+    fn render(coalescer: &mut Coalescer, timeouts: u64, spacing: Duration) -> Vec<String> {
+        let started = Instant::now();
+        (1..=timeouts)
+            .map(|n| match coalescer.timeout(n, spacing * (n - 1) as u32, started + spacing * n as u32) {
+                Action::Verbatim => format!("timeout {}", n),
+                Action::Update(line) => format!("in place: {}", line),
+                Action::Print(line) => line,
+                Action::Hide => "hidden".to_owned(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn updates_in_place_on_a_terminal() {
+        let mut coalescer = Coalescer::new(2, Duration::from_secs(10), true);
+        assert_eq!(render(&mut coalescer, 4, Duration::from_millis(1500)), vec![
+            "timeout 1",
+            "timeout 2",
+            "in place: ... 3 consecutive timeouts (last 3.0 s) ...",
+            "in place: ... 4 consecutive timeouts (last 4.5 s) ...",
+        ]);
+        // The line on the terminal is already up to date
+        assert_eq!(coalescer.end_streak(), None);
+    }
+
+    #[test]
+    fn prints_every_interval_otherwise() {
+        let mut coalescer = Coalescer::new(2, Duration::from_secs(3), false);
+        assert_eq!(render(&mut coalescer, 7, Duration::from_secs(1)), vec![
+            "timeout 1",
+            "timeout 2",
+            "... 3 consecutive timeouts (last 2.0 s) ...",
+            "hidden",
+            "hidden",
+            "... 6 consecutive timeouts (last 5.0 s) ...",
+            "hidden",
+        ]);
+        assert_eq!(coalescer.end_streak(), Some("... 7 consecutive timeouts (last 6.0 s) ...".to_owned()));
+        assert_eq!(coalescer.end_streak(), None);
+    }
+
+    #[test]
+    fn starts_over_after_a_reply() {
+        let mut coalescer = Coalescer::new(1, Duration::from_secs(3), false);
+        render(&mut coalescer, 3, Duration::from_secs(1));
+        coalescer.end_streak();
+        assert_eq!(render(&mut coalescer, 2, Duration::from_secs(1)), vec![
+            "timeout 1",
+            "... 2 consecutive timeouts (last 1.0 s) ...",
+        ]);
+    }
+}
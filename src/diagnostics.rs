@@ -0,0 +1,111 @@
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use crate::output::Output;
+use crate::ping::{PnetTransport, ProbeId};
+
+const PING_GROUP_RANGE: &str = "/proc/sys/net/ipv4/ping_group_range";
+
+// Linux's capability number for CAP_NET_RAW
+const CAP_NET_RAW: u32 = 13;
+
+fn features() -> Vec<&'static str> {
+    let mut features = Vec::new();
+    if cfg!(feature = "simulate") {
+        features.push("simulate");
+    }
+    features
+}
+
+fn describe(result: io::Result<String>) -> String {
+    match result {
+        Ok(s) => s,
+        Err(e) => format!("unavailable ({})", e),
+    }
+}
+
+fn raw_socket(addr: IpAddr) -> io::Result<String> {
+    PnetTransport::new(addr, 64, ProbeId::default()).map(|_| "ok".to_owned())
+}
+
+#[cfg(unix)]
+fn dgram_socket(addr: IpAddr) -> io::Result<String> {
+    let (domain, protocol) = match addr {
+        IpAddr::V4(_) => (libc::AF_INET, libc::IPPROTO_ICMP),
+        IpAddr::V6(_) => (libc::AF_INET6, libc::IPPROTO_ICMPV6),
+    };
+
+    let fd = unsafe { libc::socket(domain, libc::SOCK_DGRAM, protocol) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    unsafe { libc::close(fd) };
+
+    Ok("ok".to_owned())
+}
+
+#[cfg(not(unix))]
+fn dgram_socket(_addr: IpAddr) -> io::Result<String> {
+    Err(io::Error::new(io::ErrorKind::Other, "not supported on this platform"))
+}
+
+#[cfg(unix)]
+pub fn running_as_root() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+#[cfg(not(unix))]
+pub fn running_as_root() -> bool {
+    false
+}
+
+#[cfg(unix)]
+fn privileges() -> io::Result<String> {
+    if running_as_root() {
+        return Ok("root".to_owned());
+    }
+
+    // The effective capabilities are only exposed on Linux
+    let status = fs::read_to_string("/proc/self/status")?;
+    let caps = status.lines()
+        .find_map(|l| l.strip_prefix("CapEff:"))
+        .and_then(|caps| u64::from_str_radix(caps.trim(), 16).ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no CapEff line in /proc/self/status"))?;
+
+    Ok(if caps & (1 << CAP_NET_RAW) != 0 {
+        "CAP_NET_RAW".to_owned()
+    } else {
+        "none".to_owned()
+    })
+}
+
+#[cfg(not(unix))]
+fn privileges() -> io::Result<String> {
+    Err(io::Error::new(io::ErrorKind::Other, "not supported on this platform"))
+}
+
+fn ping_group_range() -> io::Result<String> {
+    fs::read_to_string(PING_GROUP_RANGE)
+        .map(|range| range.split_whitespace().collect::<Vec<_>>().join(" "))
+}
+
+// Prints what this binary was built with and what it's allowed to do on this
+// machine. Every check reports its own failure instead of stopping the others.
+pub fn print_capabilities(out: &mut Output) -> io::Result<()> {
+    let v4 = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    let v6 = IpAddr::V6(Ipv6Addr::LOCALHOST);
+
+    let features = features();
+
+    out.result("Backend: pnet")?;
+    out.result(format!("Features: {}", if features.is_empty() { "none".to_owned() } else { features.join(", ") }))?;
+    out.result(format!("Raw ICMP socket: {}", describe(raw_socket(v4))))?;
+    out.result(format!("Raw ICMPv6 socket: {}", describe(raw_socket(v6))))?;
+    out.result(format!("Datagram ICMP socket: {}", describe(dgram_socket(v4))))?;
+    out.result(format!("Datagram ICMPv6 socket: {}", describe(dgram_socket(v6))))?;
+    out.result(format!("Privileges: {}", describe(privileges())))?;
+    out.result(format!("ping_group_range: {}", describe(ping_group_range())))?;
+
+    Ok(())
+}
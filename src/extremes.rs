@@ -0,0 +1,157 @@
+use std::time::{Duration, SystemTime};
+
+use crate::output::{clock_time, decimal};
+use crate::pinger::RttSpread;
+
+// Which probe something happened to, and when it was sent, to find it in
+// other logs
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Moment {
+    pub seq: u16,
+    pub at: SystemTime,
+}
+
+impl Moment {
+    // Like `seq 1042, 14:22:31`
+    fn describe(self) -> String {
+        format!("seq {}, {}", self.seq, clock_time(self.at))
+    }
+}
+
+// Where in a run the fastest and slowest replies and the longest outage were.
+// Like the minimum and maximum rtt, these are only ever of the whole run.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Extremes {
+    pub fastest: Option<Moment>,
+    pub slowest: Option<Moment>,
+    // The first probe of the longest run of lost ones, and how long it was
+    // until the next reply
+    pub longest_outage: Option<(Moment, Duration)>,
+    // The first probe of the run of lost ones going on now
+    current_outage: Option<Moment>,
+}
+
+impl Extremes {
+    // `rtts` doesn't have this reply's rtt yet
+    pub fn reply(&mut self, rtts: RttSpread, rtt_ms: f64, probe: Moment, received_at: SystemTime) {
+        self.late_reply(rtts, rtt_ms, probe);
+        self.end_outage(received_at);
+    }
+
+    // A reply to a probe that was already counted as lost, so the outages
+    // stay as they were
+    pub fn late_reply(&mut self, rtts: RttSpread, rtt_ms: f64, probe: Moment) {
+        if rtts.count == 0 || rtt_ms < rtts.min {
+            self.fastest = Some(probe);
+        }
+        if rtts.count == 0 || rtt_ms > rtts.max {
+            self.slowest = Some(probe);
+        }
+    }
+
+    pub fn lost(&mut self, probe: Moment) {
+        self.current_outage.get_or_insert(probe);
+    }
+
+    // Ends the outage going on, if there is one, like a reply would
+    pub fn end_outage(&mut self, at: SystemTime) {
+        if let Some(started) = self.current_outage.take() {
+            let lasted = at.duration_since(started.at).unwrap_or_default();
+            if self.longest_outage.map_or(true, |(_, longest)| lasted > longest) {
+                self.longest_outage = Some((started, lasted));
+            }
+        }
+    }
+
+    // Lines for the summary, like `max 412.3 ms at seq 1042, 14:22:31`
+    pub fn summary(self, rtts: RttSpread) -> Vec<String> {
+        let mut lines = Vec::new();
+        if let (Some(fastest), true) = (self.fastest, rtts.count > 0) {
+            lines.push(format!("min {} ms at {}", decimal(rtts.min, 1), fastest.describe()));
+        }
+        if let (Some(slowest), true) = (self.slowest, rtts.count > 0) {
+            lines.push(format!("max {} ms at {}", decimal(rtts.max, 1), slowest.describe()));
+        }
+        if let Some((started, lasted)) = self.longest_outage {
+            lines.push(format!("longest outage {} s from {}", decimal(lasted.as_secs_f64(), 1), started.describe()));
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    fn at(secs: u64, seq: u16) -> Moment {
+        Moment { seq, at: UNIX_EPOCH + Duration::from_secs(secs) }
+    }
+
+    // Records replies with these rtts, one a second
+    fn replies(rtts_ms: &[f64]) -> (Extremes, RttSpread) {
+        let mut extremes = Extremes::default();
+        let mut rtts = RttSpread::default();
+        for (i, ms) in rtts_ms.iter().enumerate() {
+            let probe = at(i as u64, i as u16);
+            extremes.reply(rtts, *ms, probe, probe.at);
+            rtts.record(Duration::from_secs_f64(ms / 1000.0));
+        }
+        (extremes, rtts)
+    }
+
+    #[test]
+    fn finds_the_fastest_and_slowest_replies() {
+        let (extremes, _) = replies(&[12.0, 8.0, 30.0, 8.0, 30.0, 15.0]);
+        // Ties go to the first
+        assert_eq!(extremes.fastest, Some(at(1, 1)));
+        assert_eq!(extremes.slowest, Some(at(2, 2)));
+        assert_eq!(extremes.longest_outage, None);
+
+        let (extremes, _) = replies(&[5.0]);
+        assert_eq!((extremes.fastest, extremes.slowest), (Some(at(0, 0)), Some(at(0, 0))));
+    }
+
+    #[test]
+    fn finds_the_longest_outage() {
+        let mut extremes = Extremes::default();
+        extremes.lost(at(10, 3));
+        extremes.lost(at(11, 4));
+        extremes.reply(RttSpread::default(), 5.0, at(12, 5), UNIX_EPOCH + Duration::from_secs(12));
+        extremes.lost(at(20, 13));
+        extremes.end_outage(UNIX_EPOCH + Duration::from_millis(23_500));
+        // A shorter one doesn't replace it
+        extremes.lost(at(30, 23));
+        extremes.end_outage(UNIX_EPOCH + Duration::from_secs(31));
+
+        assert_eq!(extremes.longest_outage, Some((at(20, 13), Duration::from_millis(3500))));
+    }
+
+    #[test]
+    fn leaves_outages_alone_for_late_replies() {
+        let (mut extremes, rtts) = replies(&[12.0, 8.0]);
+        extremes.lost(at(10, 3));
+        extremes.late_reply(rtts, 2500.0, at(10, 3));
+        assert_eq!(extremes.slowest, Some(at(10, 3)));
+        assert_eq!(extremes.longest_outage, None);
+
+        extremes.end_outage(UNIX_EPOCH + Duration::from_secs(12));
+        assert_eq!(extremes.longest_outage, Some((at(10, 3), Duration::from_secs(2))));
+    }
+
+    #[test]
+    fn sums_up_where_they_were() {
+        let (mut extremes, rtts) = replies(&[12.34, 412.31, 0.04]);
+        extremes.lost(at(40, 1042));
+        extremes.end_outage(UNIX_EPOCH + Duration::from_millis(44_500));
+
+        let lines = extremes.summary(rtts);
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], format!("min 0.0 ms at seq 2, {}", clock_time(at(2, 2).at)));
+        assert_eq!(lines[1], format!("max 412.3 ms at seq 1, {}", clock_time(at(1, 1).at)));
+        assert_eq!(lines[2], format!("longest outage 4.5 s from seq 1042, {}", clock_time(at(40, 1042).at)));
+
+        // Nothing to say about a run without replies or losses
+        assert!(Extremes::default().summary(RttSpread::default()).is_empty());
+    }
+}
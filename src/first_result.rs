@@ -0,0 +1,163 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use crate::icmp_error::IcmpError;
+use crate::output::{json_string, SCHEMA_VERSION, unix_timestamp, warning};
+use crate::ping::InvalidReply;
+
+pub enum Destination {
+    // Taken over while the arguments are parsed, before ping opens any
+    // sockets that could be given the same number
+    Fd(File),
+    File(PathBuf),
+}
+
+impl Destination {
+    // Fails if `fd` isn't open, rather than writing to whatever gets that
+    // number later
+    #[cfg(unix)]
+    pub fn from_fd(fd: i32) -> io::Result<Destination> {
+        use std::os::unix::io::FromRawFd;
+
+        // Hooks mustn't inherit it, or whoever reads it wouldn't see it
+        // closed after the result
+        let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+        if flags < 0 || unsafe { libc::fcntl(fd, libc::F_SETFD, flags | libc::FD_CLOEXEC) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Destination::Fd(unsafe { File::from_raw_fd(fd) }))
+    }
+
+    #[cfg(not(unix))]
+    pub fn from_fd(_fd: i32) -> io::Result<Destination> {
+        Err(io::Error::new(io::ErrorKind::Other, "file descriptors aren't supported on this platform"))
+    }
+
+    fn open(self) -> io::Result<File> {
+        match self {
+            Destination::Fd(file) => Ok(file),
+            Destination::File(path) => File::create(path),
+        }
+    }
+}
+
+pub enum Resolution {
+    Reply(u128),
+    Timeout,
+    Error(String),
+    // Also gives the numbers, for programs
+    IcmpError(IcmpError),
+}
+
+// Writes a single JSON object describing the first probe, so a supervisor can
+// make a decision without waiting for the whole run
+pub struct FirstResult {
+    target: String,
+    destination: Destination,
+}
+
+impl FirstResult {
+    pub fn new(target: impl Into<String>, destination: Destination) -> FirstResult {
+        FirstResult { target: target.into(), destination }
+    }
+
+    // `invalid` is the invalid replies received while waiting, which is always
+    // there, even if it's empty
+    fn to_json(&self, seq: u16, sent_at: SystemTime, resolution: &Resolution, invalid: &[InvalidReply]) -> String {
+        let details = match resolution {
+            Resolution::Reply(rtt) => format!(r#""result":"reply","rtt_ms":{}"#, rtt),
+            Resolution::Timeout => r#""result":"timeout""#.to_owned(),
+            Resolution::Error(e) => format!(r#""result":"error","error":{}"#, json_string(e)),
+            Resolution::IcmpError(e) => format!(r#""result":"error","error":{},"icmp_type":{},"icmp_code":{}"#,
+                json_string(&e.to_string()),
+                e.icmp_type,
+                e.code,
+            ),
+        };
+
+        let invalid = invalid.iter()
+            .map(|reply| format!(r#"{{"from":{},"reason":{}}}"#,
+                json_string(&reply.from.to_string()),
+                json_string(&reply.reason.to_string()),
+            ))
+            .collect::<Vec<_>>();
+
+        format!(r#"{{"schema_version":{},"target":{},"seq":{},"sent_at":{},{},"invalid":[{}]}}"#,
+            SCHEMA_VERSION,
+            json_string(&self.target),
+            seq,
+            unix_timestamp(sent_at),
+            details,
+            invalid.join(","),
+        )
+    }
+
+    // Failures are only warned about, since the run carries on regardless.
+    // The file is closed when this is dropped.
+    pub fn write(self, seq: u16, sent_at: SystemTime, resolution: Resolution, invalid: &[InvalidReply]) {
+        let json = self.to_json(seq, sent_at, &resolution, invalid);
+        let written = self.destination.open().and_then(|mut f| writeln!(f, "{}", json));
+
+        if let Err(e) = written {
+            warning(format!("The first result could not be written: {}", e));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{IpAddr, Ipv4Addr};
+    use crate::ping::InvalidReason;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn json(resolution: Resolution) -> String {
+        with_invalid(resolution, &[])
+    }
+
+    fn with_invalid(resolution: Resolution, invalid: &[InvalidReply]) -> String {
+        let first_result = FirstResult::new("gw", Destination::File(PathBuf::new()));
+        first_result.to_json(7, UNIX_EPOCH + Duration::from_millis(1500), &resolution, invalid)
+    }
+
+    #[test]
+    fn describes_each_resolution() {
+        assert_eq!(
+            json(Resolution::Reply(12)),
+            r#"{"schema_version":1,"target":"gw","seq":7,"sent_at":1.500000,"result":"reply","rtt_ms":12,"invalid":[]}"#,
+        );
+        assert_eq!(
+            json(Resolution::Timeout),
+            r#"{"schema_version":1,"target":"gw","seq":7,"sent_at":1.500000,"result":"timeout","invalid":[]}"#,
+        );
+        assert_eq!(
+            json(Resolution::Error("Network is unreachable".to_owned())),
+            r#"{"schema_version":1,"target":"gw","seq":7,"sent_at":1.500000,"result":"error","error":"Network is unreachable","invalid":[]}"#,
+        );
+        let error = IcmpError { from: IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)), icmp_type: 3, code: 1 };
+        assert_eq!(
+            json(Resolution::IcmpError(error)),
+            concat!(
+                r#"{"schema_version":1,"target":"gw","seq":7,"sent_at":1.500000,"result":"error","#,
+                r#""error":"Destination host unreachable from 192.0.2.1","icmp_type":3,"icmp_code":1,"invalid":[]}"#,
+            ),
+        );
+    }
+
+    #[test]
+    fn lists_the_invalid_replies() {
+        let invalid = [
+            InvalidReply { from: IpAddr::V4(Ipv4Addr::new(10, 0, 0, 9)), reason: InvalidReason::BadChecksum },
+            InvalidReply { from: IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)), reason: InvalidReason::WrongSource },
+        ];
+        assert_eq!(
+            with_invalid(Resolution::Timeout, &invalid),
+            concat!(
+                r#"{"schema_version":1,"target":"gw","seq":7,"sent_at":1.500000,"result":"timeout","invalid":["#,
+                r#"{"from":"10.0.0.9","reason":"checksum mismatch"},{"from":"192.0.2.1","reason":"wrong source"}]}"#,
+            ),
+        );
+    }
+}
@@ -0,0 +1,104 @@
+use std::time::Instant;
+
+use crate::output::decimal;
+
+// A run of lost probes with nothing between them
+struct Gap {
+    first_seq: u16,
+    last_seq: u16,
+    probes: u64,
+    started: Instant,
+}
+
+// Keeps track of the current run of lost probes, so a line describing the
+// whole gap can be printed once it's over
+#[derive(Default)]
+pub struct GapTracker {
+    current: Option<Gap>,
+}
+
+impl GapTracker {
+    pub fn lost(&mut self, seq: u16, sent_at: Instant) {
+        match &mut self.current {
+            Some(gap) => {
+                gap.last_seq = seq;
+                gap.probes += 1;
+            },
+            None => {
+                self.current = Some(Gap { first_seq: seq, last_seq: seq, probes: 1, started: sent_at });
+            },
+        }
+    }
+
+    // Ends the current gap, if there is one, and describes it, like
+    // `--- gap: icmp_seq 5–7 lost (3 probes, 1.5 s) ---`
+    pub fn end(&mut self, until: Instant) -> Option<String> {
+        self.current.take().map(|gap| {
+            let seqs = if gap.probes == 1 {
+                gap.first_seq.to_string()
+            } else {
+                format!("{}–{}", gap.first_seq, gap.last_seq)
+            };
+
+            format!("--- gap: icmp_seq {} lost ({} probe{}, {} s) ---",
+                seqs,
+                gap.probes,
+                if gap.probes == 1 { "" } else { "s" },
+                decimal(until.duration_since(gap.started).as_secs_f64(), 1),
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    // Loses the probes with `seqs`, one every half second from `start`
+    fn lose(gaps: &mut GapTracker, start: Instant, seqs: &[u16]) {
+        for (i, seq) in seqs.iter().enumerate() {
+            gaps.lost(*seq, start + Duration::from_millis(500) * i as u32);
+        }
+    }
+
+    #[test]
+    fn describes_a_single_lost_probe() {
+        let start = Instant::now();
+        let mut gaps = GapTracker::default();
+        lose(&mut gaps, start, &[4]);
+        assert_eq!(gaps.end(start + Duration::from_millis(500)).unwrap(), "--- gap: icmp_seq 4 lost (1 probe, 0.5 s) ---");
+    }
+
+    #[test]
+    fn describes_a_gap_of_several_probes() {
+        let start = Instant::now();
+        let mut gaps = GapTracker::default();
+        lose(&mut gaps, start, &[5, 6, 7]);
+        assert_eq!(gaps.end(start + Duration::from_millis(1500)).unwrap(), "--- gap: icmp_seq 5–7 lost (3 probes, 1.5 s) ---");
+    }
+
+    #[test]
+    fn describes_each_gap_once() {
+        let start = Instant::now();
+        let mut gaps = GapTracker::default();
+        // Without a gap, a reply says nothing
+        assert_eq!(gaps.end(start), None);
+
+        lose(&mut gaps, start, &[1, 2]);
+        assert!(gaps.end(start + Duration::from_secs(1)).is_some());
+        assert_eq!(gaps.end(start + Duration::from_secs(2)), None);
+
+        // The next one starts over
+        lose(&mut gaps, start + Duration::from_secs(3), &[9]);
+        assert_eq!(gaps.end(start + Duration::from_secs(4)).unwrap(), "--- gap: icmp_seq 9 lost (1 probe, 1.0 s) ---");
+    }
+
+    #[test]
+    fn describes_a_gap_across_the_sequence_wrapping_around() {
+        let start = Instant::now();
+        let mut gaps = GapTracker::default();
+        lose(&mut gaps, start, &[65534, 65535, 0, 1]);
+        assert_eq!(gaps.end(start + Duration::from_secs(2)).unwrap(), "--- gap: icmp_seq 65534–1 lost (4 probes, 2.0 s) ---");
+    }
+}
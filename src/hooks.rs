@@ -0,0 +1,304 @@
+use std::io;
+use std::process::{Child, Command, Stdio};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Sender};
+use std::thread::{self, JoinHandle, sleep};
+use std::time::{Duration, Instant};
+
+use crate::outage::Transition;
+use crate::output::warning;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// How long to wait for a hook's thread to notice it was told to stop
+const STOP_TIMEOUT: Duration = Duration::from_secs(1);
+
+// Everything a hook is told about an event, through environment variables
+pub struct Event {
+    pub target: String,
+    pub seq: u16,
+    pub loss_percent: f64,
+    pub outage: Duration,
+}
+
+impl Event {
+    fn env(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("PING_TARGET", self.target.clone()),
+            ("PING_SEQ", self.seq.to_string()),
+            ("PING_LOSS_PCT", format!("{:.2}", self.loss_percent)),
+            ("PING_OUTAGE_SECS", format!("{:.3}", self.outage.as_secs_f64())),
+        ]
+    }
+}
+
+#[derive(Clone)]
+pub struct HookConfig {
+    // Run commands with `sh -c` instead of splitting them on whitespace
+    pub shell: bool,
+    pub timeout: Duration,
+}
+
+// A command run on a background thread each time an event happens. Events
+// are queued, so runs of the same hook never overlap.
+pub struct Hook {
+    name: &'static str,
+    events: Option<Sender<Event>>,
+    thread: Option<JoinHandle<()>>,
+    // Tells the thread to kill its command and skip whatever is queued
+    stop: Arc<AtomicBool>,
+    grace: Duration,
+}
+
+impl Hook {
+    pub fn spawn(name: &'static str, command: String, config: HookConfig) -> io::Result<Hook> {
+        if !config.shell && command.split_whitespace().next().is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("The command for the '{}' flag must not be empty.", name),
+            ));
+        }
+
+        let (events, receiver) = channel::<Event>();
+        let stop = Arc::new(AtomicBool::new(false));
+        let grace = config.timeout;
+
+        let thread = {
+            let stop = stop.clone();
+            thread::Builder::new()
+                .name(name.to_owned())
+                .spawn(move || {
+                    for event in receiver {
+                        if stop.load(Ordering::Relaxed) {
+                            break;
+                        }
+                        if let Err(e) = run(&command, &config, &event, &stop) {
+                            warning(format!("The '{}' hook failed: {}", name, e));
+                        }
+                    }
+                })?
+        };
+
+        Ok(Hook { name, events: Some(events), thread: Some(thread), stop, grace })
+    }
+
+    fn trigger(&self, event: Event) {
+        if let Some(events) = &self.events {
+            let _ = events.send(event);
+        }
+    }
+
+    // Lets the queued events run for up to the hook timeout, then kills the
+    // command and skips the rest. A thread that still won't stop is left
+    // behind with a warning rather than holding up the exit.
+    fn shutdown(&mut self) {
+        let thread = match self.thread.take() {
+            Some(thread) => thread,
+            None => return,
+        };
+
+        // The thread finishes the queue once this sender is gone
+        self.events = None;
+
+        if !wait_for(&thread, self.grace) {
+            self.stop.store(true, Ordering::Relaxed);
+            if !wait_for(&thread, STOP_TIMEOUT) {
+                warning(format!("The '{}' hook didn't stop, so it was left running.", self.name));
+                return;
+            }
+        }
+
+        let _ = thread.join();
+    }
+}
+
+// Dropping the hooks (which happens when the pinger is dropped, however the
+// run ended) gives anything triggered near the end a chance to finish
+impl Drop for Hook {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+// Returns whether the thread finished within `timeout`
+fn wait_for(thread: &JoinHandle<()>, timeout: Duration) -> bool {
+    let started = Instant::now();
+    while !thread.is_finished() {
+        if started.elapsed() >= timeout {
+            return false;
+        }
+        sleep(POLL_INTERVAL);
+    }
+    true
+}
+
+fn spawn_command(command: &str, config: &HookConfig, event: &Event) -> io::Result<Child> {
+    let mut cmd = if config.shell {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg(command);
+        cmd
+    } else {
+        let mut words = command.split_whitespace();
+        let mut cmd = Command::new(words.next().unwrap_or_default());
+        cmd.args(words);
+        cmd
+    };
+
+    cmd.envs(event.env())
+        .stdin(Stdio::null())
+        .stdout(hook_stdout()?)
+        .spawn()
+}
+
+// What a hook prints goes to stderr, with everything else that isn't a
+// result, so it can't end up among the results
+fn hook_stdout() -> io::Result<Stdio> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::io::AsFd;
+
+        Ok(Stdio::from(io::stderr().as_fd().try_clone_to_owned()?))
+    }
+    #[cfg(not(unix))]
+    {
+        Ok(Stdio::null())
+    }
+}
+
+fn run(command: &str, config: &HookConfig, event: &Event, stop: &AtomicBool) -> io::Result<()> {
+    let mut child = spawn_command(command, config, event)?;
+    let started = Instant::now();
+
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return if status.success() {
+                Ok(())
+            } else {
+                Err(io::Error::new(io::ErrorKind::Other, format!("it exited with {}", status)))
+            };
+        }
+
+        if stop.load(Ordering::Relaxed) {
+            child.kill()?;
+            child.wait()?;
+            return Err(io::Error::new(io::ErrorKind::Interrupted, "it was killed because ping is exiting"));
+        }
+
+        if started.elapsed() >= config.timeout {
+            child.kill()?;
+            child.wait()?;
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("it was killed after running for {:?}", config.timeout),
+            ));
+        }
+
+        sleep(POLL_INTERVAL);
+    }
+}
+
+#[derive(Default)]
+pub struct Hooks {
+    pub on_down: Option<Hook>,
+    pub on_up: Option<Hook>,
+}
+
+impl Hooks {
+    pub fn trigger(&self, transition: Transition, event: impl FnOnce(Duration) -> Event) {
+        match transition {
+            Transition::Down(outage) => {
+                if let Some(hook) = &self.on_down {
+                    hook.trigger(event(outage));
+                }
+            },
+            Transition::Up(outage) => {
+                if let Some(hook) = &self.on_up {
+                    hook.trigger(event(outage));
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process;
+
+    fn event(seq: u16) -> Event {
+        Event {
+            target: "gw".to_owned(),
+            seq,
+            loss_percent: 37.5,
+            outage: Duration::from_millis(1500),
+        }
+    }
+
+    fn shell(timeout: Duration) -> HookConfig {
+        HookConfig { shell: true, timeout }
+    }
+
+    fn temp_file(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("ping-hook-{}-{}", name, process::id()))
+    }
+
+    #[test]
+    fn describes_the_event_in_the_environment() {
+        assert_eq!(event(5).env(), vec![
+            ("PING_TARGET", "gw".to_owned()),
+            ("PING_SEQ", "5".to_owned()),
+            ("PING_LOSS_PCT", "37.50".to_owned()),
+            ("PING_OUTAGE_SECS", "1.500".to_owned()),
+        ]);
+    }
+
+    #[test]
+    fn runs_the_command_with_the_event() {
+        let path = temp_file("env");
+        let command = format!("env | grep '^PING_' | sort > {}", path.display());
+        let hook = Hook::spawn("on_down", command, shell(Duration::from_secs(5))).unwrap();
+        hook.trigger(event(5));
+        // Waits for the queued event to run
+        drop(hook);
+
+        let env = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(env, "PING_LOSS_PCT=37.50\nPING_OUTAGE_SECS=1.500\nPING_SEQ=5\nPING_TARGET=gw\n");
+    }
+
+    #[test]
+    fn runs_never_overlap() {
+        let path = temp_file("serial");
+        let command = format!("echo start $PING_SEQ >> {0}; sleep 0.1; echo end $PING_SEQ >> {0}", path.display());
+        let hook = Hook::spawn("on_down", command, shell(Duration::from_secs(5))).unwrap();
+        for seq in 0..3 {
+            hook.trigger(event(seq));
+        }
+        drop(hook);
+
+        let log = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(log, "start 0\nend 0\nstart 1\nend 1\nstart 2\nend 2\n");
+    }
+
+    #[test]
+    fn kills_commands_that_run_too_long() {
+        let started = Instant::now();
+        let result = run("sleep 5", &shell(Duration::from_millis(100)), &event(0), &AtomicBool::new(false));
+
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::TimedOut);
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn reports_failed_commands() {
+        let config = HookConfig { shell: false, timeout: Duration::from_secs(5) };
+        assert!(run("false", &config, &event(0), &AtomicBool::new(false)).is_err());
+        assert!(run("/nonexistent/hook", &config, &event(0), &AtomicBool::new(false)).is_err());
+        assert!(run("true", &config, &event(0), &AtomicBool::new(false)).is_ok());
+        assert!(Hook::spawn("on_up", "  ".to_owned(), config).is_err());
+    }
+}
@@ -0,0 +1,174 @@
+use std::fmt;
+use std::net::IpAddr;
+
+// An ICMP or ICMPv6 error that came back in place of a reply to one of our
+// probes, like a router saying the TTL ran out
+#[derive(Clone, Copy, Debug)]
+pub struct IcmpError {
+    pub from: IpAddr,
+    pub icmp_type: u8,
+    pub code: u8,
+}
+
+impl IcmpError {
+    // Whether the ttl (the hop limit, for icmpv6) ran out on the way, as
+    // opposed to the time for putting fragments back together
+    pub fn is_ttl_exceeded(self) -> bool {
+        let ttl_exceeded = match self.from {
+            IpAddr::V4(_) => (11, 0),
+            IpAddr::V6(_) => (3, 0),
+        };
+        (self.icmp_type, self.code) == ttl_exceeded
+    }
+}
+
+// Every assigned code of the error types, as listed by IANA
+fn icmp_description(icmp_type: u8, code: u8) -> Option<&'static str> {
+    Some(match (icmp_type, code) {
+        (3, 0) => "Destination network unreachable",
+        (3, 1) => "Destination host unreachable",
+        (3, 2) => "Destination protocol unreachable",
+        (3, 3) => "Destination port unreachable",
+        (3, 4) => "Fragmentation needed and DF set",
+        (3, 5) => "Source route failed",
+        (3, 6) => "Destination network unknown",
+        (3, 7) => "Destination host unknown",
+        (3, 8) => "Source host isolated",
+        (3, 9) => "Destination network administratively prohibited",
+        (3, 10) => "Destination host administratively prohibited",
+        (3, 11) => "Destination network unreachable for type of service",
+        (3, 12) => "Destination host unreachable for type of service",
+        (3, 13) => "Communication administratively prohibited",
+        (3, 14) => "Host precedence violation",
+        (3, 15) => "Precedence cutoff in effect",
+        (4, 0) => "Source quench",
+        (5, 0) => "Redirect for the network",
+        (5, 1) => "Redirect for the host",
+        (5, 2) => "Redirect for the type of service and network",
+        (5, 3) => "Redirect for the type of service and host",
+        (11, 0) => "Time to live exceeded",
+        (11, 1) => "Fragment reassembly time exceeded",
+        (12, 0) => "Parameter problem",
+        (12, 1) => "Parameter problem: missing a required option",
+        (12, 2) => "Parameter problem: bad length",
+        _ => return None,
+    })
+}
+
+fn icmpv6_description(icmp_type: u8, code: u8) -> Option<&'static str> {
+    Some(match (icmp_type, code) {
+        (1, 0) => "No route to destination",
+        (1, 1) => "Communication with destination administratively prohibited",
+        (1, 2) => "Beyond scope of source address",
+        (1, 3) => "Address unreachable",
+        (1, 4) => "Port unreachable",
+        (1, 5) => "Source address failed ingress/egress policy",
+        (1, 6) => "Reject route to destination",
+        (1, 7) => "Error in source routing header",
+        (1, 8) => "Headers too long",
+        (2, 0) => "Packet too big",
+        (3, 0) => "Hop limit exceeded",
+        (3, 1) => "Fragment reassembly time exceeded",
+        (4, 0) => "Parameter problem: erroneous header field",
+        (4, 1) => "Parameter problem: unrecognized next header",
+        (4, 2) => "Parameter problem: unrecognized IPv6 option",
+        (4, 3) => "Parameter problem: first fragment has incomplete header chain",
+        (4, 4) => "Parameter problem: SR upper-layer header error",
+        (4, 5) => "Parameter problem: unrecognized next header type encountered by intermediate node",
+        (4, 6) => "Parameter problem: extension header too big",
+        (4, 7) => "Parameter problem: extension header chain too long",
+        (4, 8) => "Parameter problem: too many extension headers",
+        (4, 9) => "Parameter problem: too many options in extension header",
+        (4, 10) => "Parameter problem: option too big",
+        _ => return None,
+    })
+}
+
+impl fmt::Display for IcmpError {
+    // Like `Time to live exceeded from 192.0.2.1`
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = match self.from {
+            IpAddr::V4(_) => icmp_description(self.icmp_type, self.code),
+            IpAddr::V6(_) => icmpv6_description(self.icmp_type, self.code),
+        };
+
+        match description {
+            Some(description) => write!(f, "{} from {}", description, self.from),
+            None => write!(f, "ICMP type {} code {} from {}", self.icmp_type, self.code, self.from),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    // Every assigned (type, code) pair of the ICMP error messages
+    const ICMP_ASSIGNED: &[(u8, &[u8])] = &[
+        (3, &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15]),
+        (4, &[0]),
+        (5, &[0, 1, 2, 3]),
+        (11, &[0, 1]),
+        (12, &[0, 1, 2]),
+    ];
+
+    const ICMPV6_ASSIGNED: &[(u8, &[u8])] = &[
+        (1, &[0, 1, 2, 3, 4, 5, 6, 7, 8]),
+        (2, &[0]),
+        (3, &[0, 1]),
+        (4, &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10]),
+    ];
+
+    fn assert_all_described(assigned: &[(u8, &[u8])], describe: fn(u8, u8) -> Option<&'static str>) {
+        for (icmp_type, codes) in assigned {
+            for code in *codes {
+                assert!(describe(*icmp_type, *code).is_some(), "type {} code {} has no description", icmp_type, code);
+            }
+        }
+    }
+
+    #[test]
+    fn describes_every_assigned_code() {
+        assert_all_described(ICMP_ASSIGNED, icmp_description);
+        assert_all_described(ICMPV6_ASSIGNED, icmpv6_description);
+    }
+
+    #[test]
+    fn describes_nothing_unassigned() {
+        for icmp_type in 0..=255 {
+            for code in 0..=255 {
+                let assigned = |table: &[(u8, &[u8])]| table.iter().any(|(t, codes)| *t == icmp_type && codes.contains(&code));
+                assert_eq!(icmp_description(icmp_type, code).is_some(), assigned(ICMP_ASSIGNED));
+                assert_eq!(icmpv6_description(icmp_type, code).is_some(), assigned(ICMPV6_ASSIGNED));
+            }
+        }
+    }
+
+    #[test]
+    fn renders_with_the_sender() {
+        let error = IcmpError { from: IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)), icmp_type: 11, code: 0 };
+        assert_eq!(error.to_string(), "Time to live exceeded from 192.0.2.1");
+
+        let error = IcmpError { from: IpAddr::V6(Ipv6Addr::LOCALHOST), icmp_type: 1, code: 4 };
+        assert_eq!(error.to_string(), "Port unreachable from ::1");
+
+        // The same numbers mean something else for the other family
+        let error = IcmpError { from: IpAddr::V6(Ipv6Addr::LOCALHOST), icmp_type: 11, code: 0 };
+        assert_eq!(error.to_string(), "ICMP type 11 code 0 from ::1");
+    }
+
+    #[test]
+    fn knows_when_the_ttl_ran_out() {
+        let v4 = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        let v6 = IpAddr::V6(Ipv6Addr::LOCALHOST);
+        assert!(IcmpError { from: v4, icmp_type: 11, code: 0 }.is_ttl_exceeded());
+        assert!(IcmpError { from: v6, icmp_type: 3, code: 0 }.is_ttl_exceeded());
+
+        // Not reassembly running out of time, other errors, or the other family's numbers
+        assert!(!IcmpError { from: v4, icmp_type: 11, code: 1 }.is_ttl_exceeded());
+        assert!(!IcmpError { from: v6, icmp_type: 3, code: 1 }.is_ttl_exceeded());
+        assert!(!IcmpError { from: v4, icmp_type: 3, code: 0 }.is_ttl_exceeded());
+        assert!(!IcmpError { from: v6, icmp_type: 11, code: 0 }.is_ttl_exceeded());
+    }
+}
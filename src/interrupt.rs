@@ -0,0 +1,42 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// How often a sleep checks whether it was interrupted
+const SLEEP_SLICE: Duration = Duration::from_millis(50);
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn handle_interrupt(_: libc::c_int) {
+    // A second Ctrl-C means the user doesn't want to wait for the summary
+    if INTERRUPTED.swap(true, Ordering::SeqCst) {
+        unsafe { libc::_exit(130) };
+    }
+}
+
+// Makes Ctrl-C end the run cleanly instead of killing the process
+#[cfg(unix)]
+pub fn catch_interrupts() {
+    let handler: extern "C" fn(libc::c_int) = handle_interrupt;
+    unsafe { libc::signal(libc::SIGINT, handler as libc::sighandler_t) };
+}
+
+#[cfg(not(unix))]
+pub fn catch_interrupts() {}
+
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+// Like `thread::sleep`, but returns early once interrupted
+pub fn sleep(duration: Duration) {
+    let started = Instant::now();
+    while !interrupted() {
+        let remaining = duration.saturating_sub(started.elapsed());
+        if remaining == Duration::from_secs(0) {
+            break;
+        }
+        thread::sleep(remaining.min(SLEEP_SLICE));
+    }
+}
@@ -0,0 +1,127 @@
+const ELISION: &str = "…";
+
+// A piece of an output line. Segments with a `drop_rank` can be left out
+// when the line doesn't fit, lowest rank first; the rest are always shown.
+pub struct Segment {
+    text: String,
+    separator: &'static str,
+    drop_rank: Option<u8>,
+}
+
+impl Segment {
+    // `separator` goes between this segment and the one before it
+    pub fn new(separator: &'static str, text: impl Into<String>) -> Segment {
+        Segment { text: text.into(), separator, drop_rank: None }
+    }
+
+    pub fn droppable(separator: &'static str, text: impl Into<String>, drop_rank: u8) -> Segment {
+        Segment { text: text.into(), separator, drop_rank: Some(drop_rank) }
+    }
+}
+
+fn render(segments: &[Segment], dropped: &[bool]) -> String {
+    let mut line = String::new();
+    let mut elided = false;
+
+    for (i, segment) in segments.iter().enumerate() {
+        // A run of dropped segments shares one elision mark
+        let repeated_elision = dropped[i] && elided;
+
+        if !line.is_empty() && !repeated_elision {
+            line.push_str(segment.separator);
+        }
+
+        if dropped[i] {
+            if !elided {
+                line.push_str(ELISION);
+            }
+            elided = true;
+        } else {
+            line.push_str(&segment.text);
+            elided = false;
+        }
+    }
+
+    line
+}
+
+// Joins the segments into a line, dropping segments until it's at most
+// `width` characters wide, if it can be
+pub fn fit(segments: &[Segment], width: Option<usize>) -> String {
+    let mut dropped = vec![false; segments.len()];
+
+    let mut drop_order = segments.iter()
+        .enumerate()
+        .filter_map(|(i, s)| s.drop_rank.map(|rank| (rank, i)))
+        .collect::<Vec<_>>();
+    drop_order.sort();
+    let mut drop_order = drop_order.into_iter().map(|(_, i)| i);
+
+    loop {
+        let line = render(segments, &dropped);
+        if width.map(|w| line.chars().count() <= w).unwrap_or(true) {
+            return line;
+        }
+
+        match drop_order.next() {
+            Some(i) => dropped[i] = true,
+            None => return line,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Like a probe's line: the rtt and the loss are always kept
+    fn line() -> Vec<Segment> {
+        vec![
+            Segment::new(" ", "Response received:"),
+            Segment::new(" ", "12ms rtt"),
+            Segment::droppable(", ", "11 average rtt", 2),
+            Segment::new(", ", "0/4 lost (0.00%)"),
+            Segment::droppable(", ", "0.500s interval", 1),
+            Segment::droppable(" ", "(local address)", 0),
+        ]
+    }
+
+    #[test]
+    fn keeps_everything_without_a_width() {
+        let full = "Response received: 12ms rtt, 11 average rtt, 0/4 lost (0.00%), 0.500s interval (local address)";
+        assert_eq!(fit(&line(), None), full);
+        assert_eq!(fit(&line(), Some(full.len())), full);
+    }
+
+    #[test]
+    fn drops_the_lowest_ranks_first() {
+        let goldens = [
+            (93, "Response received: 12ms rtt, 11 average rtt, 0/4 lost (0.00%), 0.500s interval …"),
+            (79, "Response received: 12ms rtt, 11 average rtt, 0/4 lost (0.00%), …"),
+            (60, "Response received: 12ms rtt, …, 0/4 lost (0.00%), …"),
+        ];
+        for (width, golden) in &goldens {
+            let fitted = fit(&line(), Some(*width));
+            assert_eq!(fitted, *golden, "at {} columns", width);
+            assert!(fitted.chars().count() <= *width);
+        }
+    }
+
+    #[test]
+    fn never_drops_the_rest() {
+        // Too narrow for even the segments that are always kept
+        assert_eq!(fit(&line(), Some(10)), "Response received: 12ms rtt, …, 0/4 lost (0.00%), …");
+    }
+
+    #[test]
+    fn shares_one_mark_between_neighbouring_drops() {
+        let segments = vec![
+            Segment::new(" ", "a"),
+            Segment::droppable(" ", "bbbb", 0),
+            Segment::droppable(" ", "cccc", 1),
+            Segment::new(" ", "d"),
+        ];
+        assert_eq!(fit(&segments, Some(8)), "a … d");
+        assert_eq!(fit(&segments, Some(10)), "a … cccc d");
+    }
+}
@@ -0,0 +1,76 @@
+use std::net::IpAddr;
+
+use pnet::datalink::interfaces;
+
+pub const LOCAL_NOTE: &str = "local address — replies do not traverse the network";
+
+// The addresses of every interface on this machine
+pub fn local_addresses() -> Vec<IpAddr> {
+    interfaces().into_iter()
+        .flat_map(|i| i.ips)
+        .map(|network| network.ip())
+        .collect()
+}
+
+// Whether pings to `addr` would be answered by this machine's own network stack
+pub fn is_local(addr: IpAddr, local_addrs: &[IpAddr]) -> bool {
+    // A v4-mapped address is the v4 address it maps
+    let addr = match addr {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(addr, IpAddr::V4),
+        IpAddr::V4(_) => addr,
+    };
+    addr.is_loopback() || addr.is_unspecified() || local_addrs.contains(&addr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    // Loopback, an ethernet interface with both families, and a tunnel
+    fn fake_interfaces() -> Vec<IpAddr> {
+        ["127.0.0.1", "::1", "192.0.2.10", "2001:db8::10", "fe80::1", "198.51.100.1"].iter().map(|s| addr(s)).collect()
+    }
+
+    #[test]
+    fn loopback_is_local() {
+        for s in &["127.0.0.1", "127.1.2.3", "::1"] {
+            assert!(is_local(addr(s), &fake_interfaces()), "{}", s);
+            // Even when the interface list couldn't be read
+            assert!(is_local(addr(s), &[]), "{}", s);
+        }
+    }
+
+    #[test]
+    fn unspecified_is_local() {
+        assert!(is_local(addr("0.0.0.0"), &[]));
+        assert!(is_local(addr("::"), &[]));
+    }
+
+    #[test]
+    fn addresses_of_other_interfaces_are_local() {
+        for s in &["192.0.2.10", "2001:db8::10", "fe80::1", "198.51.100.1"] {
+            assert!(is_local(addr(s), &fake_interfaces()), "{}", s);
+            assert!(!is_local(addr(s), &[]), "{}", s);
+        }
+    }
+
+    #[test]
+    fn other_addresses_are_not() {
+        for s in &["192.0.2.11", "2001:db8::11", "fe80::2", "8.8.8.8", "::2"] {
+            assert!(!is_local(addr(s), &fake_interfaces()), "{}", s);
+        }
+    }
+
+    #[test]
+    fn v4_mapped_addresses_are_their_v4_address() {
+        assert!(is_local(addr("::ffff:127.0.0.1"), &[]));
+        assert!(is_local(addr("::ffff:192.0.2.10"), &fake_interfaces()));
+        assert!(!is_local(addr("::ffff:192.0.2.11"), &fake_interfaces()));
+        // Not the v4-compatible form, which is long deprecated
+        assert!(!is_local(addr("::192.0.2.10"), &fake_interfaces()));
+    }
+}
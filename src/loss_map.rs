@@ -0,0 +1,189 @@
+use std::collections::VecDeque;
+use std::fmt;
+
+pub const DEFAULT_LOSS_MAP_SIZE: usize = 1000;
+
+const PROBES_PER_LINE: usize = 50;
+const PROBES_PER_GROUP: usize = 10;
+
+// The probe number in front of each line, and the space after it
+const LINE_PREFIX_WIDTH: usize = 7;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Reply,
+    Lost,
+    // An icmp error came back instead of a reply
+    Error,
+}
+
+impl Outcome {
+    fn symbol(self) -> char {
+        match self {
+            Outcome::Reply => '.',
+            Outcome::Lost => 'x',
+            Outcome::Error => 'e',
+        }
+    }
+
+    fn from_symbol(symbol: char) -> Option<Outcome> {
+        match symbol {
+            '.' => Some(Outcome::Reply),
+            'x' => Some(Outcome::Lost),
+            'e' => Some(Outcome::Error),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct LossMap {
+    outcomes: VecDeque<Outcome>,
+    capacity: usize,
+    // The number of probes that were dropped from the front of the map
+    dropped: u64,
+    probes_per_line: usize,
+}
+
+impl LossMap {
+    pub fn new(capacity: usize) -> LossMap {
+        LossMap {
+            outcomes: VecDeque::new(),
+            capacity,
+            dropped: 0,
+            probes_per_line: PROBES_PER_LINE,
+        }
+    }
+
+    // Puts fewer groups on each line if 50 probes don't fit in `width`
+    // columns, though never less than one group
+    pub fn width(mut self, width: Option<usize>) -> LossMap {
+        if let Some(width) = width {
+            let groups = (width.saturating_sub(LINE_PREFIX_WIDTH) + 1) / (PROBES_PER_GROUP + 1);
+            self.probes_per_line = groups.max(1).min(PROBES_PER_LINE / PROBES_PER_GROUP) * PROBES_PER_GROUP;
+        }
+        self
+    }
+
+    pub fn push(&mut self, outcome: Outcome) {
+        if self.capacity == 0 {
+            self.dropped += 1;
+            return;
+        }
+
+        if self.outcomes.len() == self.capacity {
+            self.outcomes.pop_front();
+            self.dropped += 1;
+        }
+        self.outcomes.push_back(outcome);
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    pub fn symbols(&self) -> String {
+        self.outcomes.iter().map(|o| o.symbol()).collect()
+    }
+
+    // Replaces the map with one saved using `dropped` and `symbols`. Returns
+    // false if the symbols are invalid.
+    pub fn restore(&mut self, dropped: u64, symbols: &str) -> bool {
+        let outcomes = symbols.chars().map(Outcome::from_symbol).collect::<Option<Vec<_>>>();
+
+        match outcomes {
+            Some(outcomes) => {
+                self.outcomes.clear();
+                self.dropped = dropped;
+                for outcome in outcomes {
+                    self.push(outcome);
+                }
+                true
+            },
+            None => false,
+        }
+    }
+}
+
+// Each line holds 50 probes in groups of 10, or fewer groups on narrow
+// terminals, prefixed with the number of the first probe on that line:
+//
+//      1 .......... ....x..... .......... .......... ..........
+//     51 ..........
+impl fmt::Display for LossMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.dropped > 0 {
+            writeln!(f, "(the first {} probes are not shown)", self.dropped)?;
+        }
+
+        for (i, outcome) in self.outcomes.iter().enumerate() {
+            if i % self.probes_per_line == 0 {
+                if i != 0 {
+                    writeln!(f)?;
+                }
+                write!(f, "{:>6} ", self.dropped + i as u64 + 1)?;
+            } else if i % PROBES_PER_GROUP == 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{}", outcome.symbol())?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(symbols: &str, capacity: usize) -> LossMap {
+        let mut map = LossMap::new(capacity);
+        for symbol in symbols.chars() {
+            map.push(Outcome::from_symbol(symbol).unwrap());
+        }
+        map
+    }
+
+    #[test]
+    fn renders_groups_and_lines() {
+        let symbols = format!("{}x{}e{}", ".".repeat(14), ".".repeat(40), ".".repeat(4));
+        assert_eq!(
+            map(&symbols, 100).to_string(),
+            "     1 .......... ....x..... .......... .......... ..........\n    51 .....e....",
+        );
+    }
+
+    #[test]
+    fn keeps_only_the_last_probes() {
+        let map = map("xx...", 3);
+        assert_eq!(map.to_string(), "(the first 2 probes are not shown)\n     3 ...");
+        assert_eq!(map.dropped(), 2);
+        assert_eq!(map.symbols(), "...");
+    }
+
+    #[test]
+    fn wraps_to_the_width() {
+        let symbols = ".".repeat(25);
+        // Exactly two groups fit in 7 + 10 + 1 + 10 columns
+        assert_eq!(
+            map(&symbols, 100).width(Some(28)).to_string(),
+            "     1 .......... ..........\n    21 .....",
+        );
+        assert_eq!(
+            map(&symbols, 100).width(Some(27)).to_string(),
+            "     1 ..........\n    11 ..........\n    21 .....",
+        );
+        // Too narrow for even one group, and wider than needed
+        assert_eq!(map(&symbols, 100).width(Some(5)).probes_per_line, PROBES_PER_GROUP);
+        assert_eq!(map(&symbols, 100).width(Some(500)).probes_per_line, PROBES_PER_LINE);
+    }
+
+    #[test]
+    fn restores_saved_symbols() {
+        let mut restored = LossMap::new(10);
+        assert!(restored.restore(4, ".xe."));
+        assert_eq!(restored.dropped(), 4);
+        assert_eq!(restored.symbols(), ".xe.");
+        assert!(!restored.restore(0, ".?"));
+    }
+}
@@ -1,86 +1,193 @@
-use clap::{App, Arg};
+use clap::{App, Arg, ArgMatches, ErrorKind};
 
 use dns_lookup::lookup_host;
 
 use std::io;
 use std::net::IpAddr;
 use std::process::exit;
-use std::thread::sleep;
-use std::time::{Duration, Instant};
+use std::time::Duration;
+
+mod accept;
+use accept::AcceptFrom;
+
+mod coalesce;
+use coalesce::{Coalescer, DEFAULT_COALESCE_AFTER, DEFAULT_COALESCE_INTERVAL};
+
+mod diagnostics;
+use diagnostics::{print_capabilities, running_as_root};
+
+mod extremes;
+
+mod first_result;
+use first_result::{Destination, FirstResult};
+
+mod gap;
+
+mod hooks;
+use hooks::{Hook, HookConfig, Hooks};
+
+mod icmp_error;
+
+mod interrupt;
+use interrupt::catch_interrupts;
+
+mod layout;
+
+mod local;
+use local::{is_local, local_addresses, LOCAL_NOTE};
+
+mod loss_map;
+use loss_map::{DEFAULT_LOSS_MAP_SIZE, LossMap};
+
+mod oneline;
+use oneline::{oneline, ONELINE_INTERVAL};
+
+mod outage;
+use outage::DEFAULT_DOWN_AFTER;
+
+mod output;
+use output::{use_decimal_comma, Output};
 
 mod ping;
-use ping::{create_channels, PACKET_DATA_SIZE, packet_iter, send_ping};
+use ping::{PnetTransport, ProbeId, Transport};
+
+mod pinger;
+use pinger::{DEFAULT_INTERVAL, DEFAULT_TIMEOUT, grace_for_timeout, Pinger, RttOnly, timeout_for_rtt};
+
+mod ramp;
+use ramp::{MIN_UNPRIVILEGED_INTERVAL, Ramp};
+
+mod scope;
+use scope::{prefer, Scope};
+
+#[cfg(feature = "simulate")]
+mod simulate;
+#[cfg(feature = "simulate")]
+use simulate::{DEFAULT_SEED, Profile, SimulatedTransport};
+
+mod state;
+use state::StateFile;
+
+mod terminal;
+use terminal::{stdout_is_tty, stdout_width};
+
+mod timing;
+
+mod usage;
+use usage::resource_usage;
+
+mod validate;
+use validate::validate;
+
+mod verdict;
+use verdict::Requirement;
+
+// Exit statuses, as in other pings: a run exits with 0 if any probe got a
+// reply, or 1 if none did
+const EXIT_NO_REPLIES: i32 = 1;
+// Something went wrong, like the host not resolving
+const EXIT_ERROR: i32 = 2;
+// Flags that can't be used together
+const EXIT_USAGE: i32 = 2;
 
 const DEFAULT_TTL: u8 = 64;
-const DEFAULT_WAIT: u64 = 2;
 
-#[derive(Clone, Copy, Debug, Default)]
-struct PingStats {
-    num_sent: u64,
-    num_received: u64,
-    total_rtt: u128,
-}
+// Runs planned to take longer than this were probably given the wrong count
+const LONG_RUN_WARNING: Duration = Duration::from_secs(365 * 24 * 60 * 60);
 
-impl PingStats {
-    pub fn avg_rtt(self) -> u128 {
-        if self.num_received != 0 {
-            self.total_rtt / self.num_received as u128
-        } else {
-            0
-        }
-    }
+// What --rtt-only writes for a lost probe, unless told otherwise
+const DEFAULT_LOSS_VALUE: &str = "-1";
+const DEFAULT_HOOK_TIMEOUT: Duration = Duration::from_secs(10);
 
-    pub fn total_percent_loss(self) -> f64 {
-        1.0 - self.num_received as f64 / self.num_sent as f64
-    }
+fn takes_over_a_year(count: u64, interval: Duration) -> bool {
+    // In floating point, since the count can be anything up to u64::MAX
+    count as f64 * interval.as_secs_f64() > LONG_RUN_WARNING.as_secs_f64()
+}
 
-    pub fn total_lost(self) -> u64 {
-        self.num_sent - self.num_received
-    }
+// Parses a duration given in seconds, like `2` or `1.5`, or with an explicit
+// unit, like `2s` or `500ms`
+pub fn parse_duration(s: &str) -> Option<Duration> {
+    let (number, scale) = if let Some(ms) = s.strip_suffix("ms") {
+        (ms, 1000.0)
+    } else {
+        (s.strip_suffix('s').unwrap_or(s), 1.0)
+    };
 
-    fn print_stats_for_rtt(self, rtt: u128) {
-        println!("Response received: {}ms rtt, {} average rtt, {}/{} lost ({:.2}%)",
-            rtt,
-            self.avg_rtt(),
-            self.total_lost(),
-            self.num_sent,
-            self.total_percent_loss() * 100.0,
-        );
-    }
+    // Values too large for a `Duration` are as invalid as negative ones
+    number.parse::<f64>()
+        .ok()
+        .and_then(|n| Duration::try_from_secs_f64(n / scale).ok())
+}
 
-    fn print_stats_for_timeout(self) {
-        println!("Response timed out: {} average rtt, {}/{} lost ({:.2}%)",
-            self.avg_rtt(),
-            self.total_lost(),
-            self.num_sent,
-            self.total_percent_loss() * 100.0,
-        );
-    }
+// The help texts that are worked out from the defaults, kept here since
+// clap only borrows them
+struct HelpTexts {
+    ttl: String,
+    timeout: String,
+    down_after: String,
+    hook_timeout: String,
+    coalesce_after: String,
+    coalesce_interval: String,
+    loss_map_size: String,
 }
 
-fn ping_app() -> io::Result<()> {
-    let ttl_help = format!("The time to live for the icmp echo request, in seconds. Default is {}.", DEFAULT_TTL);
-    let timeout_help = format!("The number of seconds to wait for a reply. Default is {}.", DEFAULT_WAIT);
+impl HelpTexts {
+    fn new() -> HelpTexts {
+        HelpTexts {
+            ttl: format!("The time to live for the icmp echo request, in hops. Default is {}.", DEFAULT_TTL),
+            timeout: format!(
+                "How long to wait for a reply, in seconds or with an 's' or 'ms' suffix. Default is {}s.",
+                DEFAULT_TIMEOUT.as_secs_f64(),
+            ),
+            down_after: format!(
+                "The number of probes in a row that must be lost for the target to be considered down. Default is {}.",
+                DEFAULT_DOWN_AFTER,
+            ),
+            hook_timeout: format!(
+                "How long a hook may run for before it is killed, in seconds or with an 's' or 'ms' suffix. Default is {}s.",
+                DEFAULT_HOOK_TIMEOUT.as_secs_f64(),
+            ),
+            coalesce_after: format!(
+                "The number of timeouts in a row to print before collapsing the rest into one line. Default is {}.",
+                DEFAULT_COALESCE_AFTER,
+            ),
+            coalesce_interval: format!(
+                "How often to reprint the collapsed timeout line when not writing to a terminal. Default is {}s.",
+                DEFAULT_COALESCE_INTERVAL.as_secs_f64(),
+            ),
+            loss_map_size: format!(
+                "The number of probes kept by the loss map of an unbounded run. Default is {}.",
+                DEFAULT_LOSS_MAP_SIZE,
+            ),
+        }
+    }
+}
 
-    let config = App::new("ping")
+fn app(help: &HelpTexts) -> App {
+    let app = App::new("ping")
         .arg(Arg::with_name("address")
             .takes_value(true)
-            .required(true)
+            .required_unless("capabilities")
             .help("The ip or hostname to ping")
         )
         .arg(Arg::with_name("ttl")
             .takes_value(true)
             .required(false)
-            .help(&ttl_help)
+            .help(&help.ttl)
             .short("t")
             .long("ttl")
         )
+        .arg(Arg::with_name("ttl_exceeded_ok")
+            .takes_value(false)
+            .required(false)
+            .help("Count a router saying the ttl ran out as a response from that hop, for probing a hop on purpose with a small ttl.")
+            .long("ttl-exceeded-ok")
+        )
         .arg(Arg::with_name("ipv4")
             .takes_value(false)
             .required(false)
             .help("Force ping to use IPv4.")
             .short("4")
-            .conflicts_with("ipv6")
         )
         .arg(Arg::with_name("ipv6")
             .takes_value(false)
@@ -88,13 +195,42 @@ fn ping_app() -> io::Result<()> {
             .help("Force ping to use IPv6.")
             .short("6")
         )
+        .arg(Arg::with_name("prefer_scope")
+            .takes_value(true)
+            .required(false)
+            .help("When the hostname has several addresses, prefer one in this scope: private, global, or link-local.")
+            .long("prefer-scope")
+            .value_name("scope")
+            .possible_values(&["private", "global", "link-local"])
+        )
+        .arg(Arg::with_name("verbose")
+            .takes_value(false)
+            .required(false)
+            .help("Explain the choices made before pinging, like which address was picked, and sum up the other icmp packets received along with the replies at the end.")
+            .short("v")
+            .long("verbose")
+        )
         .arg(Arg::with_name("timeout")
             .takes_value(true)
             .required(false)
-            .help(&timeout_help)
+            .help(&help.timeout)
             .short("W")
             .long("wait")
         )
+        .arg(Arg::with_name("rtt_expected")
+            .takes_value(true)
+            .required(false)
+            .help("The usual rtt of the link, in milliseconds, for slow links like satellite. Unless --wait is given, the timeout is 3 times this.")
+            .long("rtt-expected")
+            .value_name("ms")
+        )
+        .arg(Arg::with_name("late_grace")
+            .takes_value(true)
+            .required(false)
+            .help("How long after the timeout a reply still counts as received, in seconds or with an 's' or 'ms' suffix. The probe's line still says it was lost. Default is 0, or twice the timeout with --rtt-expected.")
+            .long("late-grace")
+            .value_name("duration")
+        )
         .arg(Arg::with_name("packet_count")
             .takes_value(true)
             .required(false)
@@ -102,24 +238,364 @@ fn ping_app() -> io::Result<()> {
             .short("c")
             .long("count")
         )
-        .get_matches();
+        .arg(Arg::with_name("require")
+            .takes_value(true)
+            .required(false)
+            .help("Exit with status 1 unless at least <N> of the <M> probes got replies. Needs --count or --max-seq, and <M> must match the number of probes.")
+            .long("require")
+            .value_name("N/M")
+        )
+        .arg(Arg::with_name("interval_ramp")
+            .takes_value(true)
+            .required(false)
+            .help("Change the time between probes linearly from <start> to <end> over <duration>, then report the loss at each rate. Each is in seconds or has an 's' or 'ms' suffix.")
+            .long("interval-ramp")
+            .value_name("start:end:duration")
+        )
+        .arg(Arg::with_name("loss_map")
+            .takes_value(false)
+            .required(false)
+            .help("Print a map of which probes got replies, were lost or got icmp errors after the last packet has been sent. Its lines are shortened to fit --width too.")
+            .long("loss-map")
+        )
+        .arg(Arg::with_name("loss_map_size")
+            .takes_value(true)
+            .required(false)
+            .help(&help.loss_map_size)
+            .long("loss-map-size")
+        )
+        .arg(Arg::with_name("on_down")
+            .takes_value(true)
+            .required(false)
+            .help("A command to run when the target goes down.")
+            .long("on-down")
+            .value_name("command")
+        )
+        .arg(Arg::with_name("on_up")
+            .takes_value(true)
+            .required(false)
+            .help("A command to run when the target comes back up.")
+            .long("on-up")
+            .value_name("command")
+        )
+        .arg(Arg::with_name("down_after")
+            .takes_value(true)
+            .required(false)
+            .help(&help.down_after)
+            .long("down-after")
+        )
+        .arg(Arg::with_name("hook_timeout")
+            .takes_value(true)
+            .required(false)
+            .help(&help.hook_timeout)
+            .long("hook-timeout")
+        )
+        .arg(Arg::with_name("hook_shell")
+            .takes_value(false)
+            .required(false)
+            .help("Run the --on-down and --on-up commands with 'sh -c' instead of splitting them on whitespace.")
+            .long("hook-shell")
+        )
+        .arg(Arg::with_name("id_hex")
+            .takes_value(true)
+            .required(false)
+            .help("The identifier for the icmp echo requests, in hexadecimal. Default is the process id, like iputils' ping uses.")
+            .long("id-hex")
+            .value_name("id")
+        )
+        .arg(Arg::with_name("id")
+            .takes_value(true)
+            .required(false)
+            .help("The identifier for the icmp echo requests, in decimal, like the one shown in a capture.")
+            .long("id")
+        )
+        .arg(Arg::with_name("strict_id")
+            .takes_value(false)
+            .required(false)
+            .help("Only accept replies with the identifier the requests were sent with. By default a reply that matches in all but its identifier is accepted, since some NATs rewrite it, and a warning is printed.")
+            .long("strict-id")
+        )
+        .arg(Arg::with_name("accept_from")
+            .takes_value(true)
+            .required(false)
+            .help("Also count echo replies from these addresses or ranges, separated by commas, like 192.0.2.1,198.51.100.0/24, for targets like anycast services that answer from other addresses. The summary then shows how many replies came from each source.")
+            .long("accept-from")
+            .value_name("addrs")
+        )
+        .arg(Arg::with_name("show_invalid")
+            .takes_value(false)
+            .required(false)
+            .help("Print a line for each reply that can't be trusted, like one with a bad checksum or from the wrong source. They're skipped either way, and counted in the summary.")
+            .long("show-invalid")
+        )
+        .arg(Arg::with_name("seq_start")
+            .takes_value(true)
+            .required(false)
+            .help("The sequence number of the first probe. Default is 0.")
+            .long("seq-start")
+        )
+        .arg(Arg::with_name("max_seq")
+            .takes_value(true)
+            .required(false)
+            .help("Stop after the probe with this sequence number. Numbers above 65535 carry on past the wraparound, so '--seq-start 65530 --max-seq 65540' sends 65530 to 65535, then 0 to 4.")
+            .long("max-seq")
+        )
+        .arg(Arg::with_name("show_send_time")
+            .takes_value(false)
+            .required(false)
+            .help("Start each line with the time the probe was sent, in seconds since the unix epoch.")
+            .long("show-send-time")
+        )
+        .arg(Arg::with_name("no_local_warn")
+            .takes_value(false)
+            .required(false)
+            .help("Don't point out that the target is one of this machine's own addresses.")
+            .long("no-local-warn")
+        )
+        .arg(Arg::with_name("width")
+            .takes_value(true)
+            .required(false)
+            .help("Shorten lines to fit in <width> columns. Defaults to the terminal's width, or no limit when not writing to a terminal.")
+            .long("width")
+        )
+        .arg(Arg::with_name("first_result_fd")
+            .takes_value(true)
+            .required(false)
+            .help("Write a JSON object describing the first probe's result to file descriptor <fd>, then close it. <fd> must be above 2, and already open when ping starts.")
+            .long("first-result-fd")
+            .value_name("fd")
+        )
+        .arg(Arg::with_name("first_result_file")
+            .takes_value(true)
+            .required(false)
+            .help("Write a JSON object describing the first probe's result to <path>.")
+            .long("first-result-file")
+            .value_name("path")
+        )
+        .arg(Arg::with_name("no_coalesce")
+            .takes_value(false)
+            .required(false)
+            .help("Print a line for every timeout, instead of collapsing long runs of them into one line.")
+            .long("no-coalesce")
+        )
+        .arg(Arg::with_name("coalesce_after")
+            .takes_value(true)
+            .required(false)
+            .help(&help.coalesce_after)
+            .long("coalesce-after")
+        )
+        .arg(Arg::with_name("coalesce_interval")
+            .takes_value(true)
+            .required(false)
+            .help(&help.coalesce_interval)
+            .long("coalesce-interval")
+        )
+        .arg(Arg::with_name("state_file")
+            .takes_value(true)
+            .required(false)
+            .help("Carry on the statistics and sequence numbers saved in <path> by an earlier run, and save them there as this one goes.")
+            .long("state-file")
+            .value_name("path")
+        )
+        .arg(Arg::with_name("oneline")
+            .takes_value(false)
+            .required(false)
+            .help("Send the probes as quickly as possible and print nothing but one line summing up the run, like 'gw: 3/3 12.4ms'. Exits with status 1 if there were no replies. Needs --count or --max-seq.")
+            .long("oneline")
+        )
+        .arg(Arg::with_name("oneline_width")
+            .takes_value(true)
+            .required(false)
+            .help("Shorten the host in the --oneline summary so it fits in <width> columns.")
+            .long("oneline-width")
+            .value_name("width")
+        )
+        .arg(Arg::with_name("decimal_comma")
+            .takes_value(false)
+            .required(false)
+            .help("Write decimals in the output meant for people with a comma, like '0,50%'. Machine-readable output always uses a dot.")
+            .long("decimal-comma")
+        )
+        .arg(Arg::with_name("timing_breakdown")
+            .takes_value(false)
+            .required(false)
+            .help("End each line with how late the probe was sent and how long sending took, and sum up where the tool spent its time at the end.")
+            .long("timing-breakdown")
+        )
+        .arg(Arg::with_name("self_stats")
+            .takes_value(false)
+            .required(false)
+            .help("At the end of the run, report the cpu time and peak memory this process used. Verbose mode does too.")
+            .long("self-stats")
+        )
+        .arg(Arg::with_name("rtt_only")
+            .takes_value(false)
+            .required(false)
+            .help("Write nothing to stdout but the rtt of each reply in milliseconds, one per line, for piping into other programs.")
+            .long("rtt-only")
+        )
+        .arg(Arg::with_name("loss_value")
+            .takes_value(true)
+            .required(false)
+            .help("With --rtt-only, the line written for a lost probe. Default is -1.")
+            .long("loss-value")
+            .allow_hyphen_values(true)
+        )
+        .arg(Arg::with_name("skip_loss")
+            .takes_value(false)
+            .required(false)
+            .help("With --rtt-only, write nothing for a lost probe.")
+            .long("skip-loss")
+        )
+        .arg(Arg::with_name("capabilities")
+            .takes_value(false)
+            .required(false)
+            .help("Print the features this binary was built with and which sockets it can open, then exit.")
+            .long("capabilities")
+        );
+
+    #[cfg(feature = "simulate")]
+    let app = app
+        .arg(Arg::with_name("simulate")
+            .takes_value(true)
+            .required(false)
+            .hidden(true)
+            .help("Simulate replies instead of sending packets. <profile> is one of clean, lossy:<percent>%, bursty, jittery, flapping, or the path to a recorded run.")
+            .long("simulate")
+            .value_name("profile")
+        )
+        .arg(Arg::with_name("simulate_seed")
+            .takes_value(true)
+            .required(false)
+            .hidden(true)
+            .help("The seed for the random number generator used by --simulate.")
+            .long("simulate-seed")
+        );
+
+    app
+}
+
+// Returns the exit status
+fn ping_app(out: &mut Output) -> io::Result<i32> {
+    let help = HelpTexts::new();
+
+    // clap would exit with 1 for a usage error, which is taken to mean there
+    // were no replies
+    let config = match app(&help).get_matches_safe() {
+        Ok(config) => config,
+        Err(e) => return match e.kind {
+            ErrorKind::HelpDisplayed | ErrorKind::VersionDisplayed => {
+                out.result(e.message)?;
+                Ok(0)
+            },
+            _ => {
+                out.status(e.message);
+                Ok(EXIT_USAGE)
+            },
+        },
+    };
+
+    if let Err(e) = validate(&config) {
+        out.error(e);
+        return Ok(EXIT_USAGE);
+    }
+
+    if config.is_present("decimal_comma") {
+        use_decimal_comma();
+    }
+
+    let is_oneline = config.is_present("oneline");
+    if is_oneline {
+        out.quiet();
+    }
+
+    let oneline_width = config.value_of("oneline_width")
+        .map(str::parse::<usize>)
+        .transpose()
+        .map_err(|_|
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The value for the 'oneline_width' flag must be a positive integer.",
+            )
+        )?;
+
+    if config.is_present("capabilities") {
+        return print_capabilities(out).map(|_| 0);
+    }
+
+    // Before anything opens a descriptor of its own, like a socket for the
+    // name lookup, so the one given can't be mistaken for it
+    let first_result_destination = match config.value_of("first_result_fd") {
+        Some(fd) => {
+            let fd = fd.parse::<i32>()
+                .ok()
+                // The descriptor is closed once written, which would leave
+                // ping without stdin, stdout or stderr
+                .filter(|fd| *fd > 2)
+                .ok_or_else(||
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "The value for the 'first_result_fd' flag must be a file descriptor above 2, since it's closed after the result is written.",
+                    )
+                )?;
+            let destination = Destination::from_fd(fd).map_err(|e|
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("The file descriptor given to the 'first_result_fd' flag can't be used: {}", e),
+                )
+            )?;
+            Some(destination)
+        },
+        None => config.value_of("first_result_file").map(|path| Destination::File(path.into())),
+    };
 
     let host = config.value_of("address").unwrap();
-    let addrs = lookup_host(host)?;
+    let mut addrs = lookup_host(host)?;
+    let preferred_scope = config.value_of("prefer_scope")
+        .map(str::parse::<Scope>)
+        .transpose()?;
+    if let Some(scope) = preferred_scope {
+        prefer(&mut addrs, scope);
+    }
+
     let addr =
         if config.is_present("ipv4") {
-            addrs.into_iter().find(IpAddr::is_ipv4)
+            addrs.iter().cloned().find(IpAddr::is_ipv4)
+                .ok_or_else(|| family_mismatch(host, "-4", &addrs))?
         } else if config.is_present("ipv6") {
-            addrs.into_iter().find(IpAddr::is_ipv6)
+            addrs.iter().cloned().find(IpAddr::is_ipv6)
+                .ok_or_else(|| family_mismatch(host, "-6", &addrs))?
         } else {
-            addrs.get(0).cloned()
-        }
-        .ok_or_else(||
-            io::Error::new(
-                io::ErrorKind::NotFound,
-                format!("The hostname '{}' could not be found.", host),
-            )
-        )?;
+            addrs.first().cloned()
+                .ok_or_else(||
+                    io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("The hostname '{}' could not be found.", host),
+                    )
+                )?
+        };
+
+    if config.is_present("verbose") {
+        let candidates = addrs.iter()
+            .map(|a| format!("{} ({})", a, Scope::of(*a)))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let family =
+            if config.is_present("ipv4") {
+                "IPv4 "
+            } else if config.is_present("ipv6") {
+                "IPv6 "
+            } else {
+                ""
+            };
+        let reason = match preferred_scope {
+            Some(scope) if Scope::of(addr) == scope => format!("it's the first {} {}address", scope, family),
+            Some(scope) => format!("there are no {} {}addresses, so it's the first {}address", scope, family, family),
+            None => format!("it's the first {}address", family),
+        };
+        out.status(format!("'{}' resolved to {}", host, candidates));
+        out.status(format!("Using {}, since {}", addr, reason));
+    }
 
     let ttl = config.value_of("ttl")
         .map(str::parse)
@@ -131,16 +607,54 @@ fn ping_app() -> io::Result<()> {
             )
         )?;
 
-    let timeout = config.value_of("timeout")
-        .map(str::parse)
-        .unwrap_or(Ok(DEFAULT_WAIT))
+    let expected_rtt = config.value_of("rtt_expected")
+        .map(str::parse::<u64>)
+        .transpose()
         .map_err(|_|
             io::Error::new(
                 io::ErrorKind::InvalidInput,
-                "The value for the 'timeout' flag must be a positive integer.",
+                "The value for the 'rtt_expected' flag must be a positive integer.",
+            )
+        )?
+        .map(Duration::from_millis);
+
+    let timeout = config.value_of("timeout")
+        .map(parse_duration)
+        .unwrap_or_else(|| Some(expected_rtt.map(timeout_for_rtt).unwrap_or(DEFAULT_TIMEOUT)))
+        .ok_or_else(||
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The value for the 'timeout' flag must be a duration, like 2, 1.5s, or 500ms.",
             )
         )?;
 
+    let late_grace = config.value_of("late_grace")
+        .map(parse_duration)
+        .unwrap_or_else(|| Some(expected_rtt.map(|_| grace_for_timeout(timeout)).unwrap_or_default()))
+        .ok_or_else(||
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The value for the 'late_grace' flag must be a duration, like 2, 1.5s, or 500ms.",
+            )
+        )?;
+
+    if let Some(expected_rtt) = expected_rtt {
+        if timeout < expected_rtt {
+            out.status(format!(
+                "Warning: The timeout ({}s) is shorter than the expected rtt ({}ms), so most replies will be counted as lost.",
+                timeout.as_secs_f64(),
+                expected_rtt.as_millis(),
+            ));
+        } else {
+            out.status(format!(
+                "Expecting rtts around {}ms, with a timeout of {}s and {}s more for late replies.",
+                expected_rtt.as_millis(),
+                timeout.as_secs_f64(),
+                late_grace.as_secs_f64(),
+            ));
+        }
+    }
+
     let packets_to_send = config.value_of("packet_count")
         .map(str::parse::<u64>)
         .transpose()
@@ -151,48 +665,436 @@ fn ping_app() -> io::Result<()> {
             )
         )?;
 
-    println!("Sending pings to {}...", addr);
+    let seq_start = config.value_of("seq_start")
+        .map(str::parse::<u16>)
+        .unwrap_or(Ok(0))
+        .map_err(|_|
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The value for the 'seq_start' flag must be an integer between 0 and 65535.",
+            )
+        )?;
+
+    let max_seq = config.value_of("max_seq")
+        .map(str::parse::<u64>)
+        .transpose()
+        .map_err(|_|
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The value for the 'max_seq' flag must be a positive integer.",
+            )
+        )?;
 
-    start_pings(addr, ttl, timeout, packets_to_send)
-}
+    // With --max-seq, the run stops on the sequence number, which fixes how
+    // many probes are sent. From 0 to u64::MAX, that's one more than a u64
+    // can count. A run that long never finishes, so it's cut one probe
+    // short, but never described as if the count were exact.
+    let mut range_overflows = false;
+    let describe_count = |count: u64, overflows: bool| {
+        if overflows { format!("more than {}", count) } else { count.to_string() }
+    };
+    let packets_to_send = match max_seq {
+        Some(max_seq) => {
+            if max_seq < seq_start as u64 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("The 'max_seq' flag ({}) must not be below the 'seq_start' flag ({}).", max_seq, seq_start),
+                ));
+            }
 
-fn start_pings(addr: IpAddr, ttl: u8, timeout: u64, packets_to_send: Option<u64>) -> io::Result<()> {
-    let (mut sender, mut receiver) = create_channels(addr, ttl)?;
-    let mut packets = packet_iter(addr, &mut receiver);
+            let range = (max_seq - seq_start as u64).checked_add(1);
+            range_overflows = range.is_none();
+            let range = range.unwrap_or(u64::MAX);
+            if let Some(count) = packets_to_send.filter(|c| *c != range || range_overflows) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "The sequence numbers {} to {} cover {} probes, but the 'packet_count' flag asks for {}.",
+                        seq_start,
+                        max_seq,
+                        describe_count(range, range_overflows),
+                        count,
+                    ),
+                ));
+            }
+            Some(range)
+        },
+        None => packets_to_send,
+    };
 
-    let mut data = [0; PACKET_DATA_SIZE];
-    let mut stats = PingStats::default();
+    let requirement = config.value_of("require")
+        .map(Requirement::parse)
+        .transpose()?;
 
-    loop {
-        if packets_to_send.map(|c| stats.num_sent >= c).unwrap_or(false) {
-            break;
+    if let (Some(requirement), Some(count)) = (requirement, packets_to_send) {
+        if requirement.out_of != count || range_overflows {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "The 'require' flag is out of {} probes, but {} will be sent.",
+                    requirement.out_of,
+                    describe_count(count, range_overflows),
+                ),
+            ));
         }
+    }
 
-        send_ping(addr, &mut data, &mut sender)?;
+    let accept_from = config.value_of("accept_from")
+        .map(AcceptFrom::parse)
+        .transpose()?
+        .unwrap_or_default();
 
-        let time_sent = Instant::now();
-        stats.num_sent += 1;
+    let mut ramp = config.value_of("interval_ramp")
+        .map(Ramp::parse)
+        .transpose()?;
+    if let Some(r) = ramp {
+        if !running_as_root() {
+            if r.reaches_below(MIN_UNPRIVILEGED_INTERVAL) {
+                out.status(format!(
+                    "Warning: Without root, the interval can't go below {}s, so the ramp stops there.",
+                    MIN_UNPRIVILEGED_INTERVAL.as_secs_f64(),
+                ));
+            }
+            ramp = Some(r.with_floor(MIN_UNPRIVILEGED_INTERVAL));
+        }
+    }
+
+    let loss_map_size = config.value_of("loss_map_size")
+        .map(str::parse)
+        .unwrap_or(Ok(DEFAULT_LOSS_MAP_SIZE))
+        .map_err(|_|
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The value for the 'loss_map_size' flag must be a positive integer.",
+            )
+        )?;
 
-        let success = packets.next_with_timeout(Duration::from_secs(timeout))?;
-        let rtt = Instant::now().duration_since(time_sent).as_millis();
 
-        if success {
-            stats.total_rtt += rtt;
-            stats.num_received += 1;
-            stats.print_stats_for_rtt(rtt);
+    let down_after = config.value_of("down_after")
+        .map(str::parse)
+        .unwrap_or(Ok(DEFAULT_DOWN_AFTER))
+        .ok()
+        .filter(|n| *n > 0)
+        .ok_or_else(||
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The value for the 'down_after' flag must be an integer greater than 0.",
+            )
+        )?;
+
+    let hook_timeout = config.value_of("hook_timeout")
+        .map(parse_duration)
+        .unwrap_or(Some(DEFAULT_HOOK_TIMEOUT))
+        .ok_or_else(||
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The value for the 'hook_timeout' flag must be a duration, like 10, 1.5s, or 500ms.",
+            )
+        )?;
+
+    let identifier = config.value_of("id_hex")
+        .map(|id| u16::from_str_radix(id.trim_start_matches("0x"), 16))
+        .unwrap_or_else(|| Ok(ProbeId::process_identifier()))
+        .map_err(|_|
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The value for the 'id_hex' flag must be a hexadecimal number between 0x0000 and 0xffff.",
+            )
+        )?;
+    let identifier = config.value_of("id")
+        .map(str::parse::<u16>)
+        .unwrap_or(Ok(identifier))
+        .map_err(|_|
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The value for the 'id' flag must be an integer between 0 and 65535.",
+            )
+        )?;
+    let id = ProbeId { identifier, run_id: ProbeId::random_run_id() };
+
+    let hook_config = HookConfig {
+        shell: config.is_present("hook_shell"),
+        timeout: hook_timeout,
+    };
+    let hooks = Hooks {
+        on_down: config.value_of("on_down")
+            .map(|c| Hook::spawn("on_down", c.to_owned(), hook_config.clone()))
+            .transpose()?,
+        on_up: config.value_of("on_up")
+            .map(|c| Hook::spawn("on_up", c.to_owned(), hook_config.clone()))
+            .transpose()?,
+    };
+
+    let width = config.value_of("width")
+        .map(str::parse::<usize>)
+        .transpose()
+        .map_err(|_|
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The value for the 'width' flag must be a positive integer.",
+            )
+        )?
+        .or_else(stdout_width);
+
+    let coalesce_after = config.value_of("coalesce_after")
+        .map(str::parse)
+        .unwrap_or(Ok(DEFAULT_COALESCE_AFTER))
+        .map_err(|_|
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The value for the 'coalesce_after' flag must be a positive integer.",
+            )
+        )?;
+
+    let coalesce_interval = config.value_of("coalesce_interval")
+        .map(parse_duration)
+        .unwrap_or(Some(DEFAULT_COALESCE_INTERVAL))
+        .ok_or_else(||
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The value for the 'coalesce_interval' flag must be a duration, like 10, 1.5s, or 500ms.",
+            )
+        )?;
+
+    let local_target = !config.is_present("no_local_warn") && is_local(addr, &local_addresses());
+
+    let mut pinger = Pinger::new(host)
+        .timeout(timeout)
+        .down_after(down_after)
+        .hooks(hooks)
+        .show_send_time(config.is_present("show_send_time"))
+        .local_target(local_target)
+        .width(width)
+        .seq_start(seq_start)
+        .timing_breakdown(config.is_present("timing_breakdown"))
+        .verbose(config.is_present("verbose"))
+        .show_invalid(config.is_present("show_invalid"))
+        .ttl_exceeded_ok(config.is_present("ttl_exceeded_ok"))
+        .late_grace(late_grace);
+
+    if let Some(count) = packets_to_send {
+        pinger = pinger.count(count);
+    }
+
+    if let Some(ramp) = ramp {
+        pinger = pinger.interval_ramp(ramp);
+    }
+
+    let oneline_interval = if running_as_root() { ONELINE_INTERVAL } else { MIN_UNPRIVILEGED_INTERVAL };
+    if is_oneline {
+        pinger = pinger.quiet(true).interval(oneline_interval);
+    }
+
+    if let Some(count) = packets_to_send {
+        let interval = match ramp {
+            _ if is_oneline => oneline_interval,
+            Some(ramp) => ramp.shortest(),
+            None => DEFAULT_INTERVAL,
+        };
+
+        if takes_over_a_year(count, interval) {
+            out.status(format!(
+                "Warning: {} probes will take over a year to send. Was the count meant to be smaller?",
+                count,
+            ));
+        }
+    }
+
+    if config.is_present("rtt_only") {
+        let loss_value = if config.is_present("skip_loss") {
+            None
         } else {
-            stats.print_stats_for_timeout();
+            Some(config.value_of("loss_value").unwrap_or(DEFAULT_LOSS_VALUE).to_owned())
+        };
+        pinger = pinger.rtt_only(RttOnly { loss_value });
+    }
+
+    if let Some(path) = config.value_of("state_file") {
+        pinger = pinger.state_file(StateFile::new(path));
+    }
+
+    if !config.is_present("no_coalesce") {
+        pinger = pinger.coalesce_timeouts(Coalescer::new(coalesce_after, coalesce_interval, stdout_is_tty()));
+    }
+
+    if let Some(destination) = first_result_destination {
+        pinger = pinger.first_result(FirstResult::new(host, destination));
+    }
+
+    // A bounded run keeps every probe in the loss map, including the ones
+    // restored from a state file
+    if config.is_present("loss_map") {
+        let capacity = if packets_to_send.is_some() { usize::MAX } else { loss_map_size };
+        pinger = pinger.loss_map(LossMap::new(capacity).width(width));
+    }
+
+    let mut transport = create_transport(&config, out, addr, ttl, id, accept_from)?;
+    if local_target {
+        out.status(format!("Note: {} is a {}", addr, LOCAL_NOTE));
+    }
+    catch_interrupts();
+    let stats = pinger.run(out, transport.as_mut())?;
+
+    if config.is_present("self_stats") || config.is_present("verbose") {
+        match resource_usage() {
+            Some(usage) => out.status(usage.to_string()),
+            None => out.status("Self: resource usage isn't available on this platform"),
         }
+    }
+
+    let verdict = requirement.map(|r| r.check(stats.num_received, stats.num_sent));
+    let passed = verdict.map(|v| v.passed()).unwrap_or(stats.num_received > 0);
+    let status = if passed { 0 } else { EXIT_NO_REPLIES };
+
+    if is_oneline {
+        out.result(oneline(host, stats, oneline_width))?;
+        return Ok(status);
+    }
+
+    // With --rtt-only, stdout is kept for the rtts
+    let rtt_only = config.is_present("rtt_only");
+
+    if max_seq.is_some() && stats.num_sent > 0 {
+        let last = seq_start.wrapping_add((stats.num_sent - 1) as u16);
+        let range = format!("icmp_seq {} to {} ({} probes)", seq_start, last, stats.num_sent);
+        if rtt_only { out.status(range) } else { out.result(range)? }
+    }
+
+    if let Some(verdict) = verdict {
+        let verdict_line = format!("verdict: {}", verdict);
+        if rtt_only { out.status(verdict_line) } else { out.result(verdict_line)? }
+    }
+
+    Ok(status)
+}
+
+// Explains that `host` has no address of the family `flag` asked for, so it
+// isn't mistaken for the host not existing
+fn family_mismatch(host: &str, flag: &str, addrs: &[IpAddr]) -> io::Error {
+    let message = match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(_)) => format!("'{}' was given, but '{}' is an IPv4 address.", flag, host),
+        Ok(IpAddr::V6(_)) => format!("'{}' was given, but '{}' is an IPv6 address.", flag, host),
+        Err(_) => {
+            let (wanted, found) = if flag == "-4" { ("A", "AAAA") } else { ("AAAA", "A") };
+            format!("'{}' was given, but '{}' has no {} records ({} {} record{} found).",
+                flag,
+                host,
+                wanted,
+                addrs.len(),
+                found,
+                if addrs.len() == 1 { "" } else { "s" },
+            )
+        },
+    };
+
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
 
-        sleep(Duration::from_millis(500));
+fn create_transport(
+    config: &ArgMatches,
+    out: &mut Output,
+    addr: IpAddr,
+    ttl: u8,
+    id: ProbeId,
+    accept_from: AcceptFrom,
+) -> io::Result<Box<dyn Transport>> {
+    #[cfg(feature = "simulate")]
+    {
+        if let Some(profile) = config.value_of("simulate") {
+            let profile = Profile::from_arg(profile)?;
+            let seed = config.value_of("simulate_seed")
+                .map(str::parse)
+                .unwrap_or(Ok(DEFAULT_SEED))
+                .map_err(|_|
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "The value for the 'simulate_seed' flag must be a positive integer.",
+                    )
+                )?;
+
+            out.status(format!("Simulating pings to {}...", addr));
+
+            return Ok(Box::new(SimulatedTransport::new(profile, seed)));
+        }
     }
+    let transport = PnetTransport::new(addr, ttl, id)?
+        .strict_id(config.is_present("strict_id"))
+        .accept_from(accept_from);
 
-    Ok(())
+    out.status(format!("Sending pings to {} (id=0x{:04x}, run=0x{:08x})...", addr, id.identifier, id.run_id));
+
+    Ok(Box::new(transport))
 }
 
 fn main() {
-    if let Err(e) = ping_app() {
-        eprintln!("Error: {}", e);
-        exit(1);
+    let mut out = Output::new();
+
+    match ping_app(&mut out) {
+        // Whatever was reading the output has gone away, so there's nobody
+        // left to tell
+        Err(ref e) if e.kind() == io::ErrorKind::BrokenPipe => {},
+        Err(e) => {
+            out.error(e);
+            exit(EXIT_ERROR);
+        },
+        Ok(status) => exit(status),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_durations_in_each_unit() {
+        assert_eq!(parse_duration("2"), Some(Duration::from_secs(2)));
+        assert_eq!(parse_duration("1.5s"), Some(Duration::from_millis(1500)));
+        assert_eq!(parse_duration("500ms"), Some(Duration::from_millis(500)));
+        assert_eq!(parse_duration("0"), Some(Duration::from_secs(0)));
+    }
+
+    #[test]
+    fn rejects_durations_that_cant_be_represented() {
+        assert_eq!(parse_duration("-1"), None);
+        assert_eq!(parse_duration("1e20"), None);
+        assert_eq!(parse_duration("1e30ms"), None);
+        assert_eq!(parse_duration("inf"), None);
+        assert_eq!(parse_duration("NaN"), None);
+        assert_eq!(parse_duration("fast"), None);
+        assert_eq!(parse_duration(""), None);
+    }
+
+    fn mismatch(host: &str, flag: &str, addrs: &[&str]) -> String {
+        let addrs = addrs.iter().map(|a| a.parse().unwrap()).collect::<Vec<IpAddr>>();
+        family_mismatch(host, flag, &addrs).to_string()
+    }
+
+    #[test]
+    fn explains_literal_addresses_of_the_other_family() {
+        assert_eq!(mismatch("192.0.2.1", "-6", &["192.0.2.1"]), "'-6' was given, but '192.0.2.1' is an IPv4 address.");
+        assert_eq!(mismatch("2001:db8::1", "-4", &["2001:db8::1"]), "'-4' was given, but '2001:db8::1' is an IPv6 address.");
+    }
+
+    #[test]
+    fn counts_the_records_of_the_other_family() {
+        assert_eq!(
+            mismatch("example.org", "-6", &["192.0.2.1", "192.0.2.2"]),
+            "'-6' was given, but 'example.org' has no AAAA records (2 A records found).",
+        );
+        assert_eq!(
+            mismatch("example.org", "-4", &["2001:db8::1"]),
+            "'-4' was given, but 'example.org' has no A records (1 AAAA record found).",
+        );
+    }
+
+    #[test]
+    fn warns_about_runs_over_a_year_for_any_count() {
+        let year_at_half_a_second = 2 * LONG_RUN_WARNING.as_secs();
+        assert!(!takes_over_a_year(year_at_half_a_second, DEFAULT_INTERVAL));
+        assert!(takes_over_a_year(year_at_half_a_second + 1, DEFAULT_INTERVAL));
+        assert!(takes_over_a_year(u64::MAX, ONELINE_INTERVAL));
+        assert!(takes_over_a_year(1, Duration::MAX));
+        assert!(!takes_over_a_year(u64::MAX, Duration::ZERO));
+        assert!(!takes_over_a_year(0, Duration::MAX));
     }
 }
@@ -0,0 +1,68 @@
+use std::time::Duration;
+
+use crate::output::decimal;
+use crate::pinger::PingStats;
+
+// How far apart probes are sent with --oneline, to finish as soon as possible
+pub const ONELINE_INTERVAL: Duration = Duration::from_millis(10);
+
+// A summary of the whole run in one short line, like `gw: 3/3 12.4ms` or
+// `gw: DOWN 0/3`. When it doesn't fit in `width`, the host is shortened.
+pub fn oneline(host: &str, stats: PingStats, width: Option<usize>) -> String {
+    let numbers = if stats.num_received == 0 {
+        format!("DOWN {}/{}", stats.num_received, stats.num_sent)
+    } else {
+        format!("{}/{} {}ms", stats.num_received, stats.num_sent, decimal(stats.rtts.mean(), 1))
+    };
+
+    let room = width.map(|w| w.saturating_sub(numbers.chars().count() + ": ".len()));
+    let host = match room {
+        Some(room) if host.chars().count() > room => {
+            let mut host = host.chars().take(room.saturating_sub(1)).collect::<String>();
+            host.push('…');
+            host
+        },
+        _ => host.to_owned(),
+    };
+
+    format!("{}: {}", host, numbers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pinger::RttSpread;
+
+    fn stats(num_sent: u64, rtts_ms: &[f64]) -> PingStats {
+        let total = rtts_ms.iter().sum::<f64>();
+        PingStats {
+            num_sent,
+            num_received: rtts_ms.len() as u64,
+            total_rtt: total as u128,
+            rtts: RttSpread {
+                count: rtts_ms.len() as u64,
+                min: rtts_ms.iter().cloned().fold(f64::INFINITY, f64::min),
+                max: rtts_ms.iter().cloned().fold(0.0, f64::max),
+                total,
+                total_squares: rtts_ms.iter().map(|r| r * r).sum(),
+            },
+            ..PingStats::default()
+        }
+    }
+
+    #[test]
+    fn sums_up_the_run() {
+        assert_eq!(oneline("gw", stats(3, &[12.1, 12.4, 12.7]), None), "gw: 3/3 12.4ms");
+        assert_eq!(oneline("gw", stats(3, &[0.04]), None), "gw: 1/3 0.0ms");
+        assert_eq!(oneline("gw", stats(3, &[]), None), "gw: DOWN 0/3");
+    }
+
+    #[test]
+    fn shortens_the_host_to_fit() {
+        let stats = stats(3, &[12.1, 12.4, 12.7]);
+        assert_eq!(oneline("gateway.example.org", stats, Some(20)), "gateway…: 3/3 12.4ms");
+        assert_eq!(oneline("gateway.example.org", stats, Some(100)), "gateway.example.org: 3/3 12.4ms");
+        // The numbers are kept even if there's no room for any of the host
+        assert_eq!(oneline("gateway.example.org", stats, Some(5)), "…: 3/3 12.4ms");
+    }
+}
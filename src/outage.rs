@@ -0,0 +1,67 @@
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_DOWN_AFTER: u64 = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Transition {
+    // How long it has been since the last reply, or since the first probe
+    // if there never was one
+    Down(Duration),
+    // How long the target was down for
+    Up(Duration),
+}
+
+// Decides when the target has gone down (after `down_after` probes in a row
+// were lost) and when it has come back up (the next reply).
+pub struct OutageTracker {
+    down_after: u64,
+    consecutive_lost: u64,
+    is_down: bool,
+    last_reply: Option<Instant>,
+    started: Instant,
+}
+
+impl OutageTracker {
+    pub fn new(down_after: u64) -> OutageTracker {
+        OutageTracker {
+            down_after,
+            consecutive_lost: 0,
+            is_down: false,
+            last_reply: None,
+            started: Instant::now(),
+        }
+    }
+
+    pub fn consecutive_lost(&self) -> u64 {
+        self.consecutive_lost
+    }
+
+    // How long it has been since the last reply
+    pub fn outage(&self, now: Instant) -> Duration {
+        now.duration_since(self.last_reply.unwrap_or(self.started))
+    }
+
+    pub fn record(&mut self, success: bool) -> Option<Transition> {
+        let now = Instant::now();
+        let outage = self.outage(now);
+
+        if success {
+            self.consecutive_lost = 0;
+            self.last_reply = Some(now);
+
+            if self.is_down {
+                self.is_down = false;
+                return Some(Transition::Up(outage));
+            }
+        } else {
+            self.consecutive_lost += 1;
+
+            if !self.is_down && self.consecutive_lost >= self.down_after {
+                self.is_down = true;
+                return Some(Transition::Down(outage));
+            }
+        }
+
+        None
+    }
+}
@@ -0,0 +1,228 @@
+use std::fmt::Display;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// All output goes through here, so the rules for which stream gets what live
+// in one place:
+//
+// - Results (one line per probe and the reports printed at the end of a run)
+//   go to stdout, so they can be piped into other programs.
+// - Everything else (banners, warnings, and errors) goes to stderr.
+pub struct Output {
+    results: Box<dyn Write>,
+    // Whether the last result was written without a newline, to be
+    // overwritten in place
+    in_place: bool,
+}
+
+// Shared with `warning`, which is called from other threads
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+impl Output {
+    pub fn new() -> Output {
+        Output {
+            results: Box::new(io::stdout()),
+            in_place: false,
+        }
+    }
+
+    // Writes the results to `buffer` instead of stdout
+    #[cfg(test)]
+    pub fn to_buffer(buffer: Buffer) -> Output {
+        Output {
+            results: Box::new(buffer),
+            in_place: false,
+        }
+    }
+
+    // Stops banners, notes, and warnings from being printed. Errors are still
+    // printed.
+    pub fn quiet(&mut self) {
+        QUIET.store(true, Ordering::Relaxed);
+    }
+
+    pub fn result(&mut self, line: impl Display) -> io::Result<()> {
+        if self.in_place {
+            self.in_place = false;
+            writeln!(self.results)?;
+        }
+        writeln!(self.results, "{}", line)
+    }
+
+    // Replaces the last result written this way, for when stdout is a terminal
+    pub fn result_in_place(&mut self, line: impl Display) -> io::Result<()> {
+        self.in_place = true;
+        write!(self.results, "\r\x1b[K{}", line)?;
+        self.results.flush()
+    }
+
+    pub fn status(&mut self, line: impl Display) {
+        if !is_quiet() {
+            eprintln!("{}", line);
+        }
+    }
+
+    pub fn error(&mut self, e: impl Display) {
+        eprintln!("Error: {}", e);
+    }
+}
+
+// For messages that don't come from the thread that owns the `Output`
+pub fn warning(line: impl Display) {
+    if !is_quiet() {
+        eprintln!("Warning: {}", line);
+    }
+}
+
+// Collects the results written by tests
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct Buffer(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+#[cfg(test)]
+impl Buffer {
+    pub fn contents(&self) -> String {
+        String::from_utf8(self.0.borrow().clone()).unwrap()
+    }
+}
+
+#[cfg(test)]
+impl Write for Buffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Seconds since the unix epoch, with microseconds, like `1586822400.123456`
+pub fn unix_timestamp(t: SystemTime) -> String {
+    let since_epoch = t.duration_since(UNIX_EPOCH).unwrap_or_default();
+    format!("{}.{:06}", since_epoch.as_secs(), since_epoch.subsec_micros())
+}
+
+// The local time of day, like `14:22:31`
+pub fn clock_time(t: SystemTime) -> String {
+    let secs = t.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+
+    #[cfg(unix)]
+    {
+        let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+        let time = secs as libc::time_t;
+        if !unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+            return format!("{:02}:{:02}:{:02}", tm.tm_hour, tm.tm_min, tm.tm_sec);
+        }
+    }
+
+    // Without the time zone, UTC is the best there is
+    let of_day = secs % (24 * 60 * 60);
+    format!("{:02}:{:02}:{:02}", of_day / 3600, of_day / 60 % 60, of_day % 60)
+}
+
+// The version of the JSON output. It's bumped when a field is removed or
+// changes meaning, but not when one is added, so parsers only need to check
+// it to know whether they can still read the output. The state file has a
+// version of its own, since only ping reads it.
+pub const SCHEMA_VERSION: u32 = 1;
+
+static DECIMAL_COMMA: AtomicBool = AtomicBool::new(false);
+
+// Makes `decimal` use a comma as the decimal separator. Only numbers meant for
+// people go through `decimal`; the first-result JSON, the state file, and the
+// hook environment always use a dot, whatever the locale.
+pub fn use_decimal_comma() {
+    DECIMAL_COMMA.store(true, Ordering::Relaxed);
+}
+
+fn format_decimal(value: f64, places: usize, comma: bool) -> String {
+    let number = format!("{:.*}", places, value);
+    if comma {
+        number.replace('.', ",")
+    } else {
+        number
+    }
+}
+
+// `value` with `places` decimals, like `12.35`, or `12,35` with a decimal comma
+pub fn decimal(value: f64, places: usize) -> String {
+    format_decimal(value, places, DECIMAL_COMMA.load(Ordering::Relaxed))
+}
+
+// A JSON string literal, with the quotes
+pub fn json_string(s: &str) -> String {
+    let mut json = String::with_capacity(s.len() + 2);
+    json.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn results_after_an_in_place_one_start_a_new_line() {
+        let buffer = Buffer::default();
+        let mut out = Output::to_buffer(buffer.clone());
+
+        out.result("1").unwrap();
+        out.result_in_place("2 of 3").unwrap();
+        out.result_in_place("3 of 3").unwrap();
+        out.result("4").unwrap();
+
+        assert_eq!(buffer.contents(), "1\n\r\x1b[K2 of 3\r\x1b[K3 of 3\n4\n");
+    }
+
+    #[test]
+    fn formats_times_of_day() {
+        // The hours and minutes depend on the time zone, the seconds don't
+        let time = clock_time(UNIX_EPOCH + Duration::from_secs(3723));
+        assert_eq!(time.len(), 8, "{}", time);
+        assert!(time.ends_with(":03"), "{}", time);
+        assert!(time.split(':').all(|part| part.len() == 2 && part.parse::<u8>().is_ok()), "{}", time);
+        assert_eq!(clock_time(UNIX_EPOCH + Duration::from_secs(86_400 + 3723)), time);
+    }
+
+    #[test]
+    fn formats_decimals_with_a_dot() {
+        assert_eq!(format_decimal(12.345, 2, false), "12.35");
+        assert_eq!(format_decimal(0.5, 3, false), "0.500");
+        assert_eq!(format_decimal(100.0, 2, false), "100.00");
+        assert_eq!(format_decimal(12.6, 0, false), "13");
+        assert_eq!(format_decimal(-1.25, 1, false), "-1.2");
+    }
+
+    #[test]
+    fn formats_decimals_with_a_comma() {
+        assert_eq!(format_decimal(12.345, 2, true), "12,35");
+        assert_eq!(format_decimal(0.5, 3, true), "0,500");
+        // Never a thousands separator
+        assert_eq!(format_decimal(12345.5, 1, true), "12345,5");
+        assert_eq!(format_decimal(12.6, 0, true), "13");
+    }
+
+    #[test]
+    fn escapes_json_strings() {
+        assert_eq!(json_string("gw"), r#""gw""#);
+        assert_eq!(json_string("a\"b\\c\nd\te\u{1}"), r#""a\"b\\c\nd\te\u0001""#);
+    }
+}
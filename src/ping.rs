@@ -20,20 +20,94 @@ use pnet::transport::{
     TransportSender,
 };
 
+use std::collections::BTreeMap;
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
 use std::io;
+use std::mem;
 use std::net::IpAddr;
-use std::time::Duration;
+use std::process;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::accept::AcceptFrom;
+use crate::icmp_error::IcmpError;
+use crate::interrupt::interrupted;
 
 pub const PACKET_DATA_SIZE: usize = 64;
 
 const CHANNEL_BUFFER_SIZE: usize = 1024;
 
-fn make_icmp_ping_request(data: &mut [u8]) -> MutableEchoRequestPacket {
+// A receive timeout of zero would block forever, so the last moments of a
+// wait are rounded up to this
+const MIN_RECEIVE_TIMEOUT: Duration = Duration::from_millis(1);
+
+// `pnet` retries a receive that a signal interrupted, so waits are split into
+// pieces this long to notice a Ctrl-C promptly
+const MAX_RECEIVE_TIMEOUT: Duration = Duration::from_millis(100);
+
+// Echo requests and replies have the same layout for icmp and icmpv6:
+// type, code, and checksum, followed by the identifier, the sequence number,
+// and then the echo data, which is laid out like so:
+//
+// | offset | size | contents                                               |
+// |--------|------|--------------------------------------------------------|
+// | 0      | 8    | send time, in microseconds since the unix epoch        |
+// | 8      | 4    | run id, random for each invocation                     |
+// | 12     | ...  | zeros                                                  |
+//
+// All numbers are big-endian.
+const CHECKSUM_OFFSET: usize = 2;
+const IDENTIFIER_OFFSET: usize = 4;
+const SEQUENCE_OFFSET: usize = 6;
+const ECHO_DATA_OFFSET: usize = 8;
+const PAYLOAD_TIMESTAMP_OFFSET: usize = 0;
+const PAYLOAD_RUN_ID_OFFSET: usize = 8;
+const PAYLOAD_TIMESTAMP_SIZE: usize = 8;
+
+// An icmp error carries the start of the packet that caused it from here on:
+// the IP header, then the first 8 bytes of our echo request
+const ERROR_QUOTE_OFFSET: usize = 8;
+const IPV6_HEADER_SIZE: usize = 40;
+
+// What each of our probes carries to tell it apart from other ping traffic
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ProbeId {
+    pub identifier: u16,
+    pub run_id: u32,
+}
+
+impl ProbeId {
+    // The identifier iputils' ping uses, so concurrent runs don't collide
+    pub fn process_identifier() -> u16 {
+        process::id() as u16
+    }
+
+    // A run id that's random enough to tell concurrent runs apart
+    pub fn random_run_id() -> u32 {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u128(SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos());
+        hasher.write_u32(process::id());
+        hasher.finish() as u32
+    }
+}
+
+fn write_echo_data(data: &mut [u8], id: ProbeId) {
+    let payload = &mut data[ECHO_DATA_OFFSET..];
+
+    let sent_at = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_micros() as u64;
+    payload[PAYLOAD_TIMESTAMP_OFFSET..PAYLOAD_TIMESTAMP_OFFSET + PAYLOAD_TIMESTAMP_SIZE].copy_from_slice(&sent_at.to_be_bytes());
+    payload[PAYLOAD_RUN_ID_OFFSET..PAYLOAD_RUN_ID_OFFSET + 4].copy_from_slice(&id.run_id.to_be_bytes());
+}
+
+fn make_icmp_ping_request(data: &mut [u8], id: ProbeId, seq: u16) -> MutableEchoRequestPacket {
+    write_echo_data(data, id);
+
     let mut req = MutableEchoRequestPacket::new(data).expect("Data provided to packet was too small");
     req.set_icmp_type(IcmpTypes::EchoRequest);
 
-    req.set_identifier(0);
-    req.set_sequence_number(0);
+    req.set_identifier(id.identifier);
+    req.set_sequence_number(seq);
 
     req.set_checksum(0);
     let cs = checksum(req.packet(), 1);
@@ -42,12 +116,17 @@ fn make_icmp_ping_request(data: &mut [u8]) -> MutableEchoRequestPacket {
     req
 }
 
-fn make_icmpv6_ping_request(data: &mut [u8]) -> MutableIcmpv6Packet {
-    let mut req = MutableIcmpv6Packet::new(data).expect("Data provided to packet was too small");
-    req.set_icmpv6_type(Icmpv6Types::EchoRequest);
+fn make_icmpv6_ping_request(data: &mut [u8], id: ProbeId, seq: u16) -> MutableIcmpv6Packet {
+    write_echo_data(data, id);
 
     // The `pnet` crate doesn't have the option to set the
-    // identifier or sequence number for icmpv6 packets
+    // identifier or sequence number for icmpv6 packets, so
+    // they're written directly into the buffer
+    data[IDENTIFIER_OFFSET..IDENTIFIER_OFFSET + 2].copy_from_slice(&id.identifier.to_be_bytes());
+    data[SEQUENCE_OFFSET..SEQUENCE_OFFSET + 2].copy_from_slice(&seq.to_be_bytes());
+
+    let mut req = MutableIcmpv6Packet::new(data).expect("Data provided to packet was too small");
+    req.set_icmpv6_type(Icmpv6Types::EchoRequest);
 
     req.set_checksum(0);
     let cs = checksum(req.packet(), 1);
@@ -56,28 +135,29 @@ fn make_icmpv6_ping_request(data: &mut [u8]) -> MutableIcmpv6Packet {
     req
 }
 
-pub enum PacketIter<'a> {
+enum PacketIter<'a> {
     V4(IcmpTransportChannelIterator<'a>),
     V6(Icmpv6TransportChannelIterator<'a>),
 }
 
 impl<'a> PacketIter<'a> {
-    pub fn next_with_timeout(&mut self, t: Duration) -> io::Result<bool> {
-        match self {
-            PacketIter::V4(iter) => iter.next_with_timeout(t).map(|v| v.is_some()),
-            PacketIter::V6(iter) => iter.next_with_timeout(t).map(|v| v.is_some()),
-        }
+    // The next packet and who sent it, or `None` if nothing arrived in time
+    fn next_with_timeout(&mut self, t: Duration) -> io::Result<Option<(Vec<u8>, IpAddr)>> {
+        Ok(match self {
+            PacketIter::V4(iter) => iter.next_with_timeout(t)?.map(|(p, from)| (p.packet().to_vec(), from)),
+            PacketIter::V6(iter) => iter.next_with_timeout(t)?.map(|(p, from)| (p.packet().to_vec(), from)),
+        })
     }
 }
 
-pub fn packet_iter(addr: IpAddr, receiver: &mut TransportReceiver) -> PacketIter {
+fn packet_iter(addr: IpAddr, receiver: &mut TransportReceiver) -> PacketIter {
     match addr {
         IpAddr::V4(_) => PacketIter::V4(icmp_packet_iter(receiver)),
         IpAddr::V6(_) => PacketIter::V6(icmpv6_packet_iter(receiver)),
     }
 }
 
-pub fn create_channels(addr: IpAddr, ttl: u8) -> io::Result<(TransportSender, TransportReceiver)> {
+fn create_channels(addr: IpAddr, ttl: u8) -> io::Result<(TransportSender, TransportReceiver)> {
     Ok(match addr {
         IpAddr::V4(_) => {
             let protocol = Layer4(TransportProtocol::Ipv4(IpNextHeaderProtocols::Icmp));
@@ -98,15 +178,713 @@ pub fn create_channels(addr: IpAddr, ttl: u8) -> io::Result<(TransportSender, Tr
     })
 }
 
-pub fn send_ping(addr: IpAddr, data: &mut [u8], sender: &mut TransportSender) -> io::Result<usize> {
+fn send_ping(addr: IpAddr, id: ProbeId, seq: u16, data: &mut [u8], sender: &mut TransportSender) -> io::Result<usize> {
     match addr {
         IpAddr::V4(_) => {
-            let req = make_icmp_ping_request(data);
+            let req = make_icmp_ping_request(data, id, seq);
             sender.send_to(req, addr)
         },
         IpAddr::V6(_) => {
-            let req = make_icmpv6_ping_request(data);
+            let req = make_icmpv6_ping_request(data, id, seq);
             sender.send_to(req, addr)
         },
     }
 }
+
+fn read_u16(packet: &[u8], offset: usize) -> Option<u16> {
+    packet.get(offset..offset + 2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+}
+
+fn read_u32(packet: &[u8], offset: usize) -> Option<u32> {
+    packet.get(offset..offset + 4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+// Whether `echo` starts like the echo request we sent with `seq`, or why not
+fn check_request(echo: &[u8], request_type: u8, id: ProbeId, seq: u16) -> Result<(), Rejection> {
+    match (echo.first(), read_u16(echo, IDENTIFIER_OFFSET), read_u16(echo, SEQUENCE_OFFSET)) {
+        (Some(&t), _, _) if t != request_type => Err(Rejection::Type),
+        (Some(_), Some(identifier), _) if identifier != id.identifier => Err(Rejection::Identifier),
+        (Some(_), Some(_), Some(sequence)) if sequence != seq => Err(Rejection::Sequence),
+        (Some(_), Some(_), Some(_)) => Ok(()),
+        _ => Err(Rejection::Truncated),
+    }
+}
+
+// Why a received packet isn't about the probe
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Rejection {
+    // Neither an echo reply nor an error about an echo request, like our own
+    // request looping back
+    Type,
+    // Another ping's traffic, or another run's
+    Identifier,
+    // An echo reply from somewhere other than the target
+    Source,
+    // About an earlier probe, like a reply that came in after its timeout
+    Sequence,
+    // Too short to tell
+    Truncated,
+}
+
+// What a received packet has to do with the probe
+#[derive(Debug)]
+enum Received {
+    Reply(Reply),
+    // An echo reply that matches the probe in all but its identifier, the way
+    // replies come back through a NAT that rewrites it
+    RewrittenId(u16),
+    // A reply to the probe that didn't come back as it was sent
+    Invalid(InvalidReason),
+    // An echo reply to an earlier probe of this run, with its sequence number
+    Late(u16),
+    Rejected(Rejection),
+}
+
+// What became of a probe
+#[derive(Clone, Copy, Debug)]
+pub enum Reply {
+    Echo,
+    // Something on the way sent back an error about the probe instead
+    Error(IcmpError),
+    Timeout,
+}
+
+// What's wrong with a reply that's to the probe, but can't be trusted
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InvalidReason {
+    // Only checked for icmp, since the kernel drops icmpv6 packets with a bad
+    // checksum itself
+    BadChecksum,
+    PayloadMismatch,
+    // It came from somewhere other than the target
+    WrongSource,
+    // Everything but the send time matches, as with a reply to an earlier
+    // probe with the same sequence number, from before it wrapped around
+    StaleTimestamp,
+}
+
+impl fmt::Display for InvalidReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            InvalidReason::BadChecksum => "checksum mismatch",
+            InvalidReason::PayloadMismatch => "payload mismatch",
+            InvalidReason::WrongSource => "wrong source",
+            InvalidReason::StaleTimestamp => "stale timestamp",
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InvalidReply {
+    pub from: IpAddr,
+    pub reason: InvalidReason,
+}
+
+// An echo reply to an earlier probe, which came in after it timed out
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LateReply {
+    pub seq: u16,
+    pub received_at: Instant,
+}
+
+// A NAT on the way rewriting the identifier of our probes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IdRewrite {
+    pub expected: u16,
+    pub got: u16,
+}
+
+impl fmt::Display for IdRewrite {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "NAT appears to rewrite ICMP id: expected {:#06x}, got {:#06x}", self.expected, self.got)
+    }
+}
+
+// How many packets were skipped for each reason
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Rejections {
+    pub wrong_type: u64,
+    pub wrong_identifier: u64,
+    pub wrong_source: u64,
+    pub wrong_sequence: u64,
+    pub truncated: u64,
+}
+
+impl Rejections {
+    fn record(&mut self, rejection: Rejection) {
+        let count = match rejection {
+            Rejection::Type => &mut self.wrong_type,
+            Rejection::Identifier => &mut self.wrong_identifier,
+            Rejection::Source => &mut self.wrong_source,
+            Rejection::Sequence => &mut self.wrong_sequence,
+            Rejection::Truncated => &mut self.truncated,
+        };
+        *count += 1;
+    }
+}
+
+impl fmt::Display for Rejections {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} of another type, {} for another ping, {} from another source, {} for an earlier probe, {} too short to read",
+            self.wrong_type,
+            self.wrong_identifier,
+            self.wrong_source,
+            self.wrong_sequence,
+            self.truncated,
+        )
+    }
+}
+
+// What a transport saw while waiting for replies, besides the replies
+#[derive(Clone, Debug, Default)]
+pub struct ReceiveCounters {
+    // Every icmp packet the socket got, replies or not
+    pub received: u64,
+    pub invalid: u64,
+    pub rejections: Rejections,
+    // The first rewrite seen, if any
+    pub id_rewrite: Option<IdRewrite>,
+    // How many replies were accepted with a rewritten identifier
+    pub rewritten: u64,
+    // How many replies came from each source, kept when replies are accepted
+    // from sources other than the target
+    pub by_source: BTreeMap<IpAddr, u64>,
+}
+
+pub trait Transport {
+    fn send_ping(&mut self, data: &mut [u8], seq: u16) -> io::Result<usize>;
+
+    // Waits up to `timeout` for the reply to the last probe sent
+    fn next_reply(&mut self, timeout: Duration) -> io::Result<Reply>;
+
+    fn receive_counters(&self) -> ReceiveCounters {
+        ReceiveCounters::default()
+    }
+
+    // The invalid replies received since this was last called, which were
+    // skipped like any other packet that isn't the reply
+    fn take_invalid(&mut self) -> Vec<InvalidReply> {
+        Vec::new()
+    }
+
+    // The late replies received since this was last called, which are also
+    // counted as skipped
+    fn take_late(&mut self) -> Vec<LateReply> {
+        Vec::new()
+    }
+}
+
+// Picks the reply to the last probe out of whatever arrives, keeping count of
+// what it lets through on the way
+struct Matcher {
+    addr: IpAddr,
+    id: ProbeId,
+    // The sequence number of the last probe sent
+    seq: u16,
+    // Whether a reply with a rewritten identifier is skipped like any other
+    strict_id: bool,
+    accept_from: AcceptFrom,
+    // The last probe sent, to compare the replies with
+    sent: Vec<u8>,
+    counters: ReceiveCounters,
+    invalid: Vec<InvalidReply>,
+    late: Vec<LateReply>,
+}
+
+impl Matcher {
+    fn new(addr: IpAddr, id: ProbeId) -> Matcher {
+        Matcher {
+            addr,
+            id,
+            seq: 0,
+            strict_id: false,
+            accept_from: AcceptFrom::default(),
+            sent: Vec::new(),
+            counters: ReceiveCounters::default(),
+            invalid: Vec::new(),
+            late: Vec::new(),
+        }
+    }
+
+    // What `packet` has to do with the last probe
+    fn classify(&self, packet: &[u8], from: IpAddr) -> Received {
+        let (addr, id, seq) = (self.addr, self.id, self.seq);
+        let (echo_reply, echo_request, error_types, quote): (u8, u8, &[u8], Option<usize>) = match addr {
+            IpAddr::V4(_) => (
+                IcmpTypes::EchoReply.0,
+                IcmpTypes::EchoRequest.0,
+                &[IcmpTypes::DestinationUnreachable.0, IcmpTypes::TimeExceeded.0, IcmpTypes::ParameterProblem.0],
+                // The quoted IP header can have options, so its length is read from it
+                packet.get(ERROR_QUOTE_OFFSET).map(|b| ERROR_QUOTE_OFFSET + (b & 0x0f) as usize * 4),
+            ),
+            IpAddr::V6(_) => (
+                Icmpv6Types::EchoReply.0,
+                Icmpv6Types::EchoRequest.0,
+                &[
+                    Icmpv6Types::DestinationUnreachable.0,
+                    Icmpv6Types::PacketTooBig.0,
+                    Icmpv6Types::TimeExceeded.0,
+                    Icmpv6Types::ParameterProblem.0,
+                ],
+                Some(ERROR_QUOTE_OFFSET + IPV6_HEADER_SIZE),
+            ),
+        };
+
+        let (icmp_type, code) = match (packet.first(), packet.get(1)) {
+            (Some(&icmp_type), Some(&code)) => (icmp_type, code),
+            _ => return Received::Rejected(Rejection::Truncated),
+        };
+
+        if icmp_type == echo_reply {
+            let identifier = read_u16(packet, IDENTIFIER_OFFSET);
+            let sequence = read_u16(packet, SEQUENCE_OFFSET);
+            // The echo data comes back as it was sent, run id included
+            let run_id = read_u32(packet, ECHO_DATA_OFFSET + PAYLOAD_RUN_ID_OFFSET);
+            let from_target = from == addr || self.accept_from.contains(from);
+
+            return match (identifier, sequence, run_id) {
+                (Some(identifier), Some(sequence), Some(run_id)) if run_id == id.run_id && sequence == seq => {
+                    if !from_target {
+                        Received::Invalid(InvalidReason::WrongSource)
+                    } else if let Err(reason) = self.check_echo(packet) {
+                        Received::Invalid(reason)
+                    } else if identifier != id.identifier {
+                        Received::RewrittenId(identifier)
+                    } else {
+                        Received::Reply(Reply::Echo)
+                    }
+                },
+                _ if !from_target => Received::Rejected(Rejection::Source),
+                (Some(_), Some(_), Some(run_id)) if run_id != id.run_id => Received::Rejected(Rejection::Identifier),
+                // Its payload was that of the earlier probe, so there's only
+                // the checksum to check
+                (Some(identifier), Some(sequence), Some(_)) if identifier == id.identifier && self.checksum_matches(packet) => {
+                    Received::Late(sequence)
+                },
+                (Some(_), Some(_), Some(_)) => Received::Rejected(Rejection::Sequence),
+                _ => Received::Rejected(Rejection::Truncated),
+            };
+        }
+
+        if !error_types.contains(&icmp_type) {
+            return Received::Rejected(Rejection::Type);
+        }
+        match quote.and_then(|quote| packet.get(quote..)) {
+            Some(echo) => match check_request(echo, echo_request, id, seq) {
+                Ok(()) => Received::Reply(Reply::Error(IcmpError { from, icmp_type, code })),
+                Err(rejection) => Received::Rejected(rejection),
+            },
+            None => Received::Rejected(Rejection::Truncated),
+        }
+    }
+
+    fn checksum_matches(&self, packet: &[u8]) -> bool {
+        self.addr.is_ipv6() || read_u16(packet, CHECKSUM_OFFSET) == Some(checksum(packet, 1))
+    }
+
+    // Whether an echo reply to the last probe came back as it was sent
+    fn check_echo(&self, packet: &[u8]) -> Result<(), InvalidReason> {
+        if !self.checksum_matches(packet) {
+            return Err(InvalidReason::BadChecksum);
+        }
+
+        // There's nothing to compare with until a probe has been sent
+        let sent = match self.sent.get(ECHO_DATA_OFFSET..) {
+            Some(sent) => sent,
+            None => return Ok(()),
+        };
+        let echoed = &packet[ECHO_DATA_OFFSET..];
+        let timestamp = PAYLOAD_TIMESTAMP_OFFSET..PAYLOAD_TIMESTAMP_OFFSET + PAYLOAD_TIMESTAMP_SIZE;
+        let differs = |i: usize| sent[i] != echoed[i];
+
+        if echoed.len() != sent.len() || (0..sent.len()).filter(|i| !timestamp.contains(i)).any(differs) {
+            Err(InvalidReason::PayloadMismatch)
+        } else if timestamp.clone().any(differs) {
+            Err(InvalidReason::StaleTimestamp)
+        } else {
+            Ok(())
+        }
+    }
+
+    // The reply to the last probe that `packet` is, if it is one
+    fn reply(&mut self, packet: &[u8], from: IpAddr) -> Option<Reply> {
+        self.counters.received += 1;
+        let reply = match self.classify(packet, from) {
+            Received::Reply(reply) => reply,
+            Received::RewrittenId(got) if !self.strict_id => {
+                let expected = self.id.identifier;
+                self.counters.id_rewrite.get_or_insert(IdRewrite { expected, got });
+                self.counters.rewritten += 1;
+                Reply::Echo
+            },
+            Received::RewrittenId(_) => {
+                self.counters.rejections.record(Rejection::Identifier);
+                return None;
+            },
+            Received::Invalid(reason) => {
+                self.counters.invalid += 1;
+                self.invalid.push(InvalidReply { from, reason });
+                return None;
+            },
+            Received::Late(seq) => {
+                self.counters.rejections.record(Rejection::Sequence);
+                self.late.push(LateReply { seq, received_at: Instant::now() });
+                return None;
+            },
+            Received::Rejected(rejection) => {
+                self.counters.rejections.record(rejection);
+                return None;
+            },
+        };
+
+        if matches!(reply, Reply::Echo) && !self.accept_from.is_empty() {
+            *self.counters.by_source.entry(from).or_insert(0) += 1;
+        }
+        Some(reply)
+    }
+}
+
+pub struct PnetTransport {
+    sender: TransportSender,
+    receiver: TransportReceiver,
+    matcher: Matcher,
+}
+
+impl PnetTransport {
+    pub fn new(addr: IpAddr, ttl: u8, id: ProbeId) -> io::Result<PnetTransport> {
+        let (sender, receiver) = create_channels(addr, ttl)?;
+        Ok(PnetTransport { sender, receiver, matcher: Matcher::new(addr, id) })
+    }
+
+    // Only takes replies that carry the identifier the probes were sent with
+    pub fn strict_id(mut self, strict_id: bool) -> PnetTransport {
+        self.matcher.strict_id = strict_id;
+        self
+    }
+
+    // Also takes echo replies from these sources
+    pub fn accept_from(mut self, accept_from: AcceptFrom) -> PnetTransport {
+        self.matcher.accept_from = accept_from;
+        self
+    }
+}
+
+impl Transport for PnetTransport {
+    fn send_ping(&mut self, data: &mut [u8], seq: u16) -> io::Result<usize> {
+        self.matcher.seq = seq;
+        let sent = send_ping(self.matcher.addr, self.matcher.id, seq, data, &mut self.sender)?;
+        self.matcher.sent = data.to_vec();
+        Ok(sent)
+    }
+
+    // Packets that aren't about the probe are skipped, but the time spent on
+    // them still counts towards the timeout, so a flood of other icmp traffic
+    // can't keep a lost probe waiting
+    fn next_reply(&mut self, timeout: Duration) -> io::Result<Reply> {
+        // A timeout too long to add to the clock means waiting for as long
+        // as it takes
+        let deadline = Instant::now().checked_add(timeout);
+        let mut packets = packet_iter(self.matcher.addr, &mut self.receiver);
+
+        loop {
+            let remaining = match deadline {
+                Some(deadline) => deadline.saturating_duration_since(Instant::now()),
+                None => MAX_RECEIVE_TIMEOUT,
+            };
+            if remaining == Duration::from_secs(0) || interrupted() {
+                return Ok(Reply::Timeout);
+            }
+
+            // Nothing arriving in time just means the deadline is checked again
+            let wait = remaining.max(MIN_RECEIVE_TIMEOUT).min(MAX_RECEIVE_TIMEOUT);
+            if let Some((packet, from)) = packets.next_with_timeout(wait)? {
+                if let Some(reply) = self.matcher.reply(&packet, from) {
+                    return Ok(reply);
+                }
+            }
+        }
+    }
+
+    fn receive_counters(&self) -> ReceiveCounters {
+        self.matcher.counters.clone()
+    }
+
+    fn take_invalid(&mut self) -> Vec<InvalidReply> {
+        mem::take(&mut self.matcher.invalid)
+    }
+
+    fn take_late(&mut self) -> Vec<LateReply> {
+        mem::take(&mut self.matcher.late)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    const V4: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 7));
+    const V6: IpAddr = IpAddr::V6(Ipv6Addr::LOCALHOST);
+    const ROUTER: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+    const ID: ProbeId = ProbeId { identifier: 0x1a2b, run_id: 0xdead_beef };
+
+    // Waiting for the reply to the probe sent to `addr` with `seq`
+    fn matcher(addr: IpAddr, id: ProbeId, seq: u16) -> Matcher {
+        Matcher { seq, ..Matcher::new(addr, id) }
+    }
+
+    fn request(addr: IpAddr, id: ProbeId, seq: u16) -> Vec<u8> {
+        let mut data = [0; PACKET_DATA_SIZE];
+        match addr {
+            IpAddr::V4(_) => make_icmp_ping_request(&mut data, id, seq).packet().to_vec(),
+            IpAddr::V6(_) => make_icmpv6_ping_request(&mut data, id, seq).packet().to_vec(),
+        }
+    }
+
+    // The reply a host would send back to `request(addr, id, seq)`
+    fn reply(addr: IpAddr, id: ProbeId, seq: u16) -> Vec<u8> {
+        as_reply(addr, request(addr, id, seq))
+    }
+
+    // `request` turned into its reply, with the checksum fixed up
+    fn as_reply(addr: IpAddr, mut packet: Vec<u8>) -> Vec<u8> {
+        packet[0] = match addr {
+            IpAddr::V4(_) => IcmpTypes::EchoReply.0,
+            IpAddr::V6(_) => Icmpv6Types::EchoReply.0,
+        };
+        fix_checksum(&mut packet);
+        packet
+    }
+
+    fn fix_checksum(packet: &mut [u8]) {
+        let cs = checksum(packet, 1);
+        packet[CHECKSUM_OFFSET..CHECKSUM_OFFSET + 2].copy_from_slice(&cs.to_be_bytes());
+    }
+
+    // An icmp error quoting the start of `request(addr, id, seq)`
+    fn error(addr: IpAddr, icmp_type: u8, code: u8, id: ProbeId, seq: u16) -> Vec<u8> {
+        let mut packet = vec![icmp_type, code, 0, 0, 0, 0, 0, 0];
+        match addr {
+            IpAddr::V4(_) => {
+                let mut header = vec![0; 20];
+                header[0] = 0x45;
+                packet.extend(header);
+            },
+            IpAddr::V6(_) => packet.extend(vec![0; IPV6_HEADER_SIZE]),
+        }
+        packet.extend(&request(addr, id, seq)[..8]);
+        packet
+    }
+
+    #[test]
+    fn accepts_our_replies() {
+        assert!(matches!(matcher(V4, ID, 5).classify(&reply(V4, ID, 5), V4), Received::Reply(Reply::Echo)));
+        assert!(matches!(matcher(V6, ID, 5).classify(&reply(V6, ID, 5), V6), Received::Reply(Reply::Echo)));
+    }
+
+    #[test]
+    fn skips_other_replies() {
+        // Another probe, another ping, another host, and our own request
+        assert!(matches!(matcher(V4, ID, 5).classify(&reply(V4, ID, 4), V4), Received::Late(4)));
+        let other_ping = ProbeId { identifier: 0x0001, run_id: 0x1234_5678 };
+        assert!(matches!(matcher(V4, ID, 5).classify(&reply(V4, other_ping, 5), V4), Received::Rejected(Rejection::Identifier)));
+        assert!(matches!(matcher(V4, ID, 5).classify(&reply(V4, other_ping, 5), ROUTER), Received::Rejected(Rejection::Source)));
+        assert!(matches!(matcher(V4, ID, 5).classify(&request(V4, ID, 5), V4), Received::Rejected(Rejection::Type)));
+        assert!(matches!(matcher(V6, ID, 5).classify(&request(V6, ID, 5), V6), Received::Rejected(Rejection::Type)));
+    }
+
+    #[test]
+    fn skips_replies_from_another_run() {
+        let other_run = ProbeId { run_id: 0x1234_5678, ..ID };
+        assert!(matches!(matcher(V4, ID, 5).classify(&reply(V4, other_run, 5), V4), Received::Rejected(Rejection::Identifier)));
+        assert!(matches!(matcher(V6, ID, 5).classify(&reply(V6, other_run, 5), V6), Received::Rejected(Rejection::Identifier)));
+    }
+
+    #[test]
+    fn tells_rewritten_identifiers_apart() {
+        let rewritten = ProbeId { identifier: 0x0000, ..ID };
+        assert!(matches!(matcher(V4, ID, 5).classify(&reply(V4, rewritten, 5), V4), Received::RewrittenId(0x0000)));
+        assert!(matches!(matcher(V6, ID, 5).classify(&reply(V6, rewritten, 5), V6), Received::RewrittenId(0x0000)));
+
+        // Only the identifier can differ
+        assert!(matches!(matcher(V4, ID, 5).classify(&reply(V4, rewritten, 4), V4), Received::Rejected(Rejection::Sequence)));
+        assert!(matches!(matcher(V4, ID, 5).classify(&reply(V4, rewritten, 5), ROUTER), Received::Invalid(InvalidReason::WrongSource)));
+    }
+
+    #[test]
+    fn accepts_rewritten_identifiers_unless_strict() {
+        let rewritten = reply(V4, ProbeId { identifier: 0x0000, ..ID }, 5);
+
+        let mut relaxed = matcher(V4, ID, 5);
+        assert!(matches!(relaxed.reply(&rewritten, V4), Some(Reply::Echo)));
+        assert!(matches!(relaxed.reply(&rewritten, V4), Some(Reply::Echo)));
+        assert!(matches!(relaxed.reply(&reply(V4, ID, 5), V4), Some(Reply::Echo)));
+        assert_eq!(relaxed.counters.rewritten, 2);
+        let rewrite = relaxed.counters.id_rewrite.unwrap();
+        assert_eq!(rewrite, IdRewrite { expected: 0x1a2b, got: 0x0000 });
+        assert_eq!(rewrite.to_string(), "NAT appears to rewrite ICMP id: expected 0x1a2b, got 0x0000");
+
+        let mut strict = Matcher { strict_id: true, ..matcher(V4, ID, 5) };
+        assert!(strict.reply(&rewritten, V4).is_none());
+        assert_eq!(strict.counters.rewritten, 0);
+        assert!(strict.counters.id_rewrite.is_none());
+    }
+
+    #[test]
+    fn accepts_replies_from_the_given_sources() {
+        const ANYCAST: IpAddr = IpAddr::V4(Ipv4Addr::new(198, 51, 100, 9));
+        let accept_from = AcceptFrom::parse("198.51.100.0/24").unwrap();
+        let mut matcher = Matcher { accept_from, ..matcher(V4, ID, 5) };
+
+        for from in &[V4, ANYCAST, ANYCAST] {
+            assert!(matches!(matcher.reply(&reply(V4, ID, 5), *from), Some(Reply::Echo)));
+        }
+        // Anything else is still skipped
+        assert!(matcher.reply(&reply(V4, ID, 5), ROUTER).is_none());
+        assert!(matcher.reply(&reply(V4, ID, 4), ANYCAST).is_none());
+
+        assert_eq!(matcher.counters.by_source.into_iter().collect::<Vec<_>>(), vec![(V4, 1), (ANYCAST, 2)]);
+    }
+
+    #[test]
+    fn tallies_sources_only_when_accepting_others() {
+        let mut matcher = matcher(V4, ID, 5);
+        assert!(matcher.reply(&reply(V4, ID, 5), V4).is_some());
+        assert!(matcher.counters.by_source.is_empty());
+    }
+
+    #[test]
+    fn keeps_late_replies_for_the_pinger() {
+        assert!(matches!(matcher(V6, ID, 5).classify(&reply(V6, ID, 3), V6), Received::Late(3)));
+        let mut corrupted = reply(V4, ID, 4);
+        corrupted[ECHO_DATA_OFFSET + PAYLOAD_TIMESTAMP_SIZE + 8] ^= 0xff;
+        assert!(matches!(matcher(V4, ID, 5).classify(&corrupted, V4), Received::Rejected(Rejection::Sequence)));
+
+        let mut matcher = matcher(V4, ID, 5);
+        assert!(matcher.reply(&reply(V4, ID, 4), V4).is_none());
+        assert!(matcher.reply(&reply(V4, ID, 2), V4).is_none());
+        assert!(matcher.reply(&reply(V4, ID, 4), ROUTER).is_none());
+        // Still counted as skipped for the probe being waited for
+        assert_eq!(matcher.counters.rejections.wrong_sequence, 2);
+        assert_eq!(matcher.late.iter().map(|late| late.seq).collect::<Vec<_>>(), vec![4, 2]);
+    }
+
+    #[test]
+    fn counts_what_it_skips() {
+        let mut matcher = matcher(V4, ID, 5);
+        let other_ping = ProbeId { identifier: 0x0001, run_id: 0x1234_5678 };
+        let mix = vec![
+            (request(V4, ID, 5), V4),
+            (request(V4, other_ping, 1), ROUTER),
+            (vec![IcmpTypes::RouterAdvertisement.0, 0, 0, 0], ROUTER),
+            (reply(V4, other_ping, 5), V4),
+            (reply(V4, other_ping, 9), V4),
+            (error(V4, IcmpTypes::DestinationUnreachable.0, 1, other_ping, 5), ROUTER),
+            (reply(V4, other_ping, 5), ROUTER),
+            (reply(V4, ID, 3), V4),
+            (reply(V4, ID, 4), V4),
+            (error(V4, IcmpTypes::TimeExceeded.0, 0, ID, 4), ROUTER),
+            (vec![IcmpTypes::EchoReply.0], V4),
+            (reply(V4, ID, 5)[..8].to_vec(), V4),
+            (reply(V4, ID, 5), V4),
+            (error(V4, IcmpTypes::TimeExceeded.0, 0, ID, 5), ROUTER),
+        ];
+        let replies = mix.iter().filter_map(|(packet, from)| matcher.reply(packet, *from)).count();
+
+        assert_eq!(replies, 2);
+        assert_eq!(matcher.counters.received, 14);
+        assert_eq!(matcher.counters.rejections, Rejections {
+            wrong_type: 3,
+            wrong_identifier: 3,
+            wrong_source: 1,
+            wrong_sequence: 3,
+            truncated: 2,
+        });
+        assert_eq!(
+            matcher.counters.rejections.to_string(),
+            "3 of another type, 3 for another ping, 1 from another source, 3 for an earlier probe, 2 too short to read",
+        );
+    }
+
+    #[test]
+    fn tells_why_replies_are_invalid() {
+        let sent = request(V4, ID, 5);
+        let sending = |addr| Matcher { sent: sent.clone(), ..matcher(addr, ID, 5) };
+        let intact = as_reply(V4, sent.clone());
+        let reason = |packet: &[u8], from| match sending(V4).classify(packet, from) {
+            Received::Invalid(reason) => Some(reason),
+            _ => None,
+        };
+        assert!(matches!(sending(V4).classify(&intact, V4), Received::Reply(Reply::Echo)));
+
+        let mut corrupted = intact.clone();
+        corrupted[PACKET_DATA_SIZE - 1] ^= 0x01;
+        assert_eq!(reason(&corrupted, V4), Some(InvalidReason::BadChecksum));
+
+        fix_checksum(&mut corrupted);
+        assert_eq!(reason(&corrupted, V4), Some(InvalidReason::PayloadMismatch));
+
+        let mut short = intact[..PACKET_DATA_SIZE - 1].to_vec();
+        fix_checksum(&mut short);
+        assert_eq!(reason(&short, V4), Some(InvalidReason::PayloadMismatch));
+
+        assert_eq!(reason(&intact, ROUTER), Some(InvalidReason::WrongSource));
+
+        let mut stale = intact.clone();
+        stale[ECHO_DATA_OFFSET + PAYLOAD_TIMESTAMP_OFFSET + 3] ^= 0x10;
+        fix_checksum(&mut stale);
+        assert_eq!(reason(&stale, V4), Some(InvalidReason::StaleTimestamp));
+
+        // Icmpv6 checksums are left to the kernel
+        let sent = request(V6, ID, 5);
+        let mut reply = as_reply(V6, sent.clone());
+        reply[CHECKSUM_OFFSET] ^= 0xff;
+        let matcher = Matcher { sent, ..matcher(V6, ID, 5) };
+        assert!(matches!(matcher.classify(&reply, V6), Received::Reply(Reply::Echo)));
+    }
+
+    #[test]
+    fn keeps_invalid_replies_for_the_pinger() {
+        let sent = request(V4, ID, 5);
+        let mut matcher = Matcher { sent: sent.clone(), ..matcher(V4, ID, 5) };
+        let intact = as_reply(V4, sent);
+        let mut corrupted = intact.clone();
+        corrupted[CHECKSUM_OFFSET] ^= 0xff;
+
+        assert!(matcher.reply(&corrupted, V4).is_none());
+        assert!(matcher.reply(&intact, ROUTER).is_none());
+        assert!(matches!(matcher.reply(&intact, V4), Some(Reply::Echo)));
+
+        assert_eq!(matcher.counters.invalid, 2);
+        assert_eq!(matcher.invalid, vec![
+            InvalidReply { from: V4, reason: InvalidReason::BadChecksum },
+            InvalidReply { from: ROUTER, reason: InvalidReason::WrongSource },
+        ]);
+        assert_eq!(matcher.counters.rejections, Rejections::default());
+    }
+
+    #[test]
+    fn matches_errors_by_the_quoted_request() {
+        let packet = error(V4, IcmpTypes::TimeExceeded.0, 0, ID, 5);
+        match matcher(V4, ID, 5).classify(&packet, ROUTER) {
+            Received::Reply(Reply::Error(e)) => assert_eq!((e.from, e.icmp_type, e.code), (ROUTER, 11, 0)),
+            other => panic!("expected an error, got {:?}", other),
+        }
+        assert!(matches!(matcher(V4, ID, 6).classify(&packet, ROUTER), Received::Rejected(Rejection::Sequence)));
+
+        let packet = error(V6, Icmpv6Types::DestinationUnreachable.0, 4, ID, 5);
+        assert!(matches!(matcher(V6, ID, 5).classify(&packet, V6), Received::Reply(Reply::Error(_))));
+        assert!(matches!(matcher(V6, ProbeId { identifier: 0x0001, ..ID }, 5).classify(&packet, V6), Received::Rejected(Rejection::Identifier)));
+    }
+
+    #[test]
+    fn skips_truncated_packets() {
+        let packet = error(V4, IcmpTypes::TimeExceeded.0, 0, ID, 5);
+        for len in 0..packet.len() {
+            assert!(matches!(matcher(V4, ID, 5).classify(&packet[..len], ROUTER), Received::Rejected(Rejection::Truncated)));
+        }
+        assert!(matches!(matcher(V4, ID, 5).classify(&reply(V4, ID, 5)[..10], V4), Received::Rejected(Rejection::Truncated)));
+    }
+}
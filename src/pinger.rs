@@ -0,0 +1,1275 @@
+use std::collections::VecDeque;
+use std::io;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::coalesce::{Action, Coalescer};
+use crate::extremes::{Extremes, Moment};
+use crate::first_result::{FirstResult, Resolution};
+use crate::gap::GapTracker;
+use crate::hooks::{Event, Hooks};
+use crate::layout::{fit, Segment};
+use crate::local::LOCAL_NOTE;
+use crate::loss_map::{LossMap, Outcome};
+use crate::outage::{DEFAULT_DOWN_AFTER, OutageTracker};
+use crate::output::{decimal, Output, unix_timestamp, warning};
+use crate::ramp::{Ramp, RampReport};
+use crate::state::{RunState, STATE_SAVE_INTERVAL, StateFile};
+use crate::timing::{ProbeTiming, TimingReport};
+use crate::icmp_error::IcmpError;
+use crate::interrupt::{interrupted, sleep};
+use crate::ping::{LateReply, PACKET_DATA_SIZE, ReceiveCounters, Reply, Transport};
+
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(2);
+pub const DEFAULT_INTERVAL: Duration = Duration::from_millis(500);
+
+// How many times the expected rtt to wait for a reply, so slower replies
+// than usual still count
+const EXPECTED_RTT_TIMEOUT_FACTOR: u32 = 3;
+
+// The timeout to use for a link whose replies usually take `expected_rtt`
+pub fn timeout_for_rtt(expected_rtt: Duration) -> Duration {
+    expected_rtt * EXPECTED_RTT_TIMEOUT_FACTOR
+}
+
+// How many times the timeout late replies still count for, when it's worked
+// out from the expected rtt
+const LATE_GRACE_TIMEOUT_FACTOR: u32 = 2;
+
+// The grace for late replies to use along with `timeout`
+pub fn grace_for_timeout(timeout: Duration) -> Duration {
+    timeout.checked_mul(LATE_GRACE_TIMEOUT_FACTOR).unwrap_or(Duration::MAX)
+}
+
+// The order parts of a line are left out in when it's too wide for the terminal
+const LOCAL_NOTE_DROP_RANK: u8 = 0;
+const INTERVAL_DROP_RANK: u8 = 1;
+const AVERAGE_DROP_RANK: u8 = 2;
+
+// What happened to a single probe
+#[derive(Clone, Copy, Debug)]
+struct ProbeResult {
+    success: bool,
+    rtt: u128,
+    elapsed: Duration,
+    // The error that came back instead of a reply, if one did. With
+    // `ttl_exceeded_ok`, a successful probe can have one too.
+    error: Option<IcmpError>,
+    sent_at: SystemTime,
+    // The interval in effect when it was sent, if it's ramping
+    interval: Option<Duration>,
+    timing: Option<ProbeTiming>,
+}
+
+// A probe that timed out, but can still be answered within the grace
+#[derive(Clone, Copy, Debug)]
+struct LostProbe {
+    seq: u16,
+    sent: Instant,
+    sent_at: SystemTime,
+}
+
+// The spread of the rtts of the replies, in milliseconds, kept precisely
+// enough for the summary at the end of a run
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RttSpread {
+    pub count: u64,
+    pub min: f64,
+    pub max: f64,
+    pub total: f64,
+    pub total_squares: f64,
+}
+
+impl RttSpread {
+    pub fn record(&mut self, rtt: Duration) {
+        let ms = rtt.as_secs_f64() * 1000.0;
+        if self.count == 0 {
+            self.min = ms;
+            self.max = ms;
+        } else {
+            self.min = self.min.min(ms);
+            self.max = self.max.max(ms);
+        }
+        self.count += 1;
+        self.total += ms;
+        self.total_squares += ms * ms;
+    }
+
+    // The minimum and maximum can't be split up, so they stay those of
+    // the whole spread
+    fn since(self, earlier: RttSpread) -> RttSpread {
+        RttSpread {
+            count: self.count - earlier.count,
+            total: self.total - earlier.total,
+            total_squares: self.total_squares - earlier.total_squares,
+            ..self
+        }
+    }
+
+    pub fn mean(self) -> f64 {
+        self.total / self.count as f64
+    }
+
+    fn stddev(self) -> f64 {
+        let mean = self.mean();
+        (self.total_squares / self.count as f64 - mean * mean).max(0.0).sqrt()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PingStats {
+    pub num_sent: u64,
+    pub num_received: u64,
+    pub total_rtt: u128,
+    pub rtts: RttSpread,
+    pub extremes: Extremes,
+}
+
+impl PingStats {
+    pub fn avg_rtt(self) -> u128 {
+        if self.num_received != 0 {
+            self.total_rtt / self.num_received as u128
+        } else {
+            0
+        }
+    }
+
+    pub fn total_percent_loss(self) -> f64 {
+        1.0 - self.num_received as f64 / self.num_sent as f64
+    }
+
+    pub fn total_lost(self) -> u64 {
+        self.num_sent - self.num_received
+    }
+
+    // The stats for what happened after `earlier` was taken
+    pub fn since(self, earlier: PingStats) -> PingStats {
+        PingStats {
+            num_sent: self.num_sent - earlier.num_sent,
+            num_received: self.num_received - earlier.num_received,
+            total_rtt: self.total_rtt - earlier.total_rtt,
+            rtts: self.rtts.since(earlier.rtts),
+            extremes: self.extremes,
+        }
+    }
+
+    fn stats_for_rtt(self, rtt: u128) -> Vec<Segment> {
+        vec![
+            Segment::new(" ", "Response received:"),
+            Segment::new(" ", format!("{}ms rtt", rtt)),
+            Segment::droppable(", ", format!("{} average rtt", self.avg_rtt()), AVERAGE_DROP_RANK),
+            Segment::new(", ", self.loss()),
+        ]
+    }
+
+    // For a router saying the ttl ran out, when that's what was asked for
+    fn stats_for_hop(self, error: IcmpError, rtt: u128) -> Vec<Segment> {
+        vec![
+            Segment::new(" ", format!("Hop response from {}:", error.from)),
+            Segment::new(" ", format!("{}ms rtt", rtt)),
+            Segment::droppable(", ", format!("{} average rtt", self.avg_rtt()), AVERAGE_DROP_RANK),
+            Segment::new(", ", self.loss()),
+        ]
+    }
+
+    fn stats_for_timeout(self) -> Vec<Segment> {
+        vec![
+            Segment::new(" ", "Response timed out:"),
+            Segment::droppable(" ", format!("{} average rtt", self.avg_rtt()), AVERAGE_DROP_RANK),
+            Segment::new(", ", self.loss()),
+        ]
+    }
+
+    fn stats_for_error(self, error: IcmpError) -> Vec<Segment> {
+        vec![
+            Segment::new(" ", format!("{}:", error)),
+            Segment::droppable(" ", format!("{} average rtt", self.avg_rtt()), AVERAGE_DROP_RANK),
+            Segment::new(", ", self.loss()),
+        ]
+    }
+
+    fn loss(self) -> String {
+        format!("{}/{} lost ({}%)",
+            self.total_lost(),
+            self.num_sent,
+            decimal(self.total_percent_loss() * 100.0, 2),
+        )
+    }
+}
+
+pub struct Pinger {
+    target: String,
+    timeout: Duration,
+    interval: Duration,
+    packets_to_send: Option<u64>,
+    seq_start: u16,
+    loss_map: Option<LossMap>,
+    down_after: u64,
+    hooks: Hooks,
+    show_send_time: bool,
+    local_target: bool,
+    width: Option<usize>,
+    first_result: Option<FirstResult>,
+    coalescer: Option<Coalescer>,
+    ramp: Option<Ramp>,
+    state_file: Option<StateFile>,
+    // How long the runs restored from the state file took
+    restored_elapsed: Duration,
+    quiet: bool,
+    rtt_only: Option<RttOnly>,
+    timing_breakdown: bool,
+    verbose: bool,
+    show_invalid: bool,
+    ttl_exceeded_ok: bool,
+    late_grace: Duration,
+    // How many replies came in within the grace, after their probe timed out
+    late_replies: u64,
+}
+
+// Prints nothing but the rtt of each reply in milliseconds, one per line
+#[derive(Clone, Debug)]
+pub struct RttOnly {
+    // What to print for a lost probe, or `None` to print nothing
+    pub loss_value: Option<String>,
+}
+
+impl Pinger {
+    pub fn new(target: impl Into<String>) -> Pinger {
+        Pinger {
+            target: target.into(),
+            timeout: DEFAULT_TIMEOUT,
+            interval: DEFAULT_INTERVAL,
+            packets_to_send: None,
+            seq_start: 0,
+            loss_map: None,
+            down_after: DEFAULT_DOWN_AFTER,
+            hooks: Hooks::default(),
+            show_send_time: false,
+            local_target: false,
+            width: None,
+            first_result: None,
+            coalescer: None,
+            ramp: None,
+            state_file: None,
+            restored_elapsed: Duration::ZERO,
+            quiet: false,
+            rtt_only: None,
+            timing_breakdown: false,
+            verbose: false,
+            show_invalid: false,
+            ttl_exceeded_ok: false,
+            late_grace: Duration::ZERO,
+            late_replies: 0,
+        }
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Pinger {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn interval(mut self, interval: Duration) -> Pinger {
+        self.interval = interval;
+        self
+    }
+
+    pub fn count(mut self, packets_to_send: u64) -> Pinger {
+        self.packets_to_send = Some(packets_to_send);
+        self
+    }
+
+    // The sequence number of the first probe. Later ones count up from it,
+    // wrapping around after 65535.
+    pub fn seq_start(mut self, seq_start: u16) -> Pinger {
+        self.seq_start = seq_start;
+        self
+    }
+
+    pub fn loss_map(mut self, loss_map: LossMap) -> Pinger {
+        self.loss_map = Some(loss_map);
+        self
+    }
+
+    pub fn down_after(mut self, down_after: u64) -> Pinger {
+        self.down_after = down_after;
+        self
+    }
+
+    pub fn hooks(mut self, hooks: Hooks) -> Pinger {
+        self.hooks = hooks;
+        self
+    }
+
+    pub fn show_send_time(mut self, show_send_time: bool) -> Pinger {
+        self.show_send_time = show_send_time;
+        self
+    }
+
+    // Marks every line to say the replies come from this machine
+    pub fn local_target(mut self, local_target: bool) -> Pinger {
+        self.local_target = local_target;
+        self
+    }
+
+    // Lines wider than this are shortened, if they can be
+    pub fn width(mut self, width: Option<usize>) -> Pinger {
+        self.width = width;
+        self
+    }
+
+    pub fn first_result(mut self, first_result: FirstResult) -> Pinger {
+        self.first_result = Some(first_result);
+        self
+    }
+
+    pub fn coalesce_timeouts(mut self, coalescer: Coalescer) -> Pinger {
+        self.coalescer = Some(coalescer);
+        self
+    }
+
+    pub fn interval_ramp(mut self, ramp: Ramp) -> Pinger {
+        self.ramp = Some(ramp);
+        self
+    }
+
+    // The run's stats are restored from this file, and saved to it periodically
+    // and at the end of the run
+    pub fn state_file(mut self, state_file: StateFile) -> Pinger {
+        self.state_file = Some(state_file);
+        self
+    }
+
+    // Prints nothing while running, not even the reports at the end
+    pub fn quiet(mut self, quiet: bool) -> Pinger {
+        self.quiet = quiet;
+        self
+    }
+
+    pub fn rtt_only(mut self, rtt_only: RttOnly) -> Pinger {
+        self.rtt_only = Some(rtt_only);
+        self
+    }
+
+    pub fn timing_breakdown(mut self, timing_breakdown: bool) -> Pinger {
+        self.timing_breakdown = timing_breakdown;
+        self
+    }
+
+    // Also sums up what the socket received besides the replies
+    pub fn verbose(mut self, verbose: bool) -> Pinger {
+        self.verbose = verbose;
+        self
+    }
+
+    // Prints a line for each invalid reply as it arrives
+    pub fn show_invalid(mut self, show_invalid: bool) -> Pinger {
+        self.show_invalid = show_invalid;
+        self
+    }
+
+    // Counts a router saying the ttl ran out as a response from that hop,
+    // for when the ttl was kept low on purpose
+    pub fn ttl_exceeded_ok(mut self, ttl_exceeded_ok: bool) -> Pinger {
+        self.ttl_exceeded_ok = ttl_exceeded_ok;
+        self
+    }
+
+    // Still counts replies that come in up to this long after their probe
+    // timed out, though the probe's line already said it was lost
+    pub fn late_grace(mut self, late_grace: Duration) -> Pinger {
+        self.late_grace = late_grace;
+        self
+    }
+
+    fn restore_state(&mut self, out: &mut Output) -> PingStats {
+        let state_file = match &self.state_file {
+            Some(state_file) => state_file,
+            None => return PingStats::default(),
+        };
+
+        match state_file.load(self.loss_map.as_mut()) {
+            Ok(Some(state)) => {
+                let gap = SystemTime::now().duration_since(state.saved_at).unwrap_or_default();
+                out.status(format!(
+                    "Restored {} probes from '{}', which was saved {}s ago. The statistics don't cover that gap.",
+                    state.stats.num_sent,
+                    state_file.path().display(),
+                    gap.as_secs(),
+                ));
+                // The numbering carries on where the saved run stopped
+                if let Some(next_seq) = state.next_seq {
+                    self.seq_start = next_seq;
+                }
+                self.restored_elapsed = state.elapsed;
+                state.stats
+            },
+            Ok(None) => PingStats::default(),
+            Err(e) => {
+                out.status(format!("Warning: Ignoring the state file '{}', since {}.", state_file.path().display(), e));
+                PingStats::default()
+            },
+        }
+    }
+
+    fn save_state(&self, out: &mut Output, stats: PingStats, next_seq: u16, elapsed: Duration) {
+        if let Some(state_file) = &self.state_file {
+            let state = RunState {
+                stats,
+                saved_at: SystemTime::now(),
+                next_seq: Some(next_seq),
+                elapsed: self.restored_elapsed + elapsed,
+            };
+            if let Err(e) = state_file.save(&state, self.loss_map.as_ref()) {
+                out.status(format!("Warning: The state file '{}' could not be saved: {}", state_file.path().display(), e));
+            }
+        }
+    }
+
+    // Like the summary iputils' ping ends with
+    fn summary(&self, stats: PingStats, elapsed: Duration, counters: &ReceiveCounters) -> Vec<String> {
+        let loss = if stats.num_sent == 0 { 0.0 } else { stats.total_percent_loss() * 100.0 };
+
+        let mut lines = vec![
+            format!("--- {} ping statistics ---", self.target),
+            format!("{} packets transmitted, {} received,{} {}% packet loss, time {}ms",
+                stats.num_sent,
+                stats.num_received,
+                if counters.invalid > 0 { format!(" {} invalid,", counters.invalid) } else { String::new() },
+                decimal(loss, 2),
+                elapsed.as_millis(),
+            ),
+        ];
+
+        if self.late_replies > 0 {
+            lines.push(format!("{} replies came in late, within the {}s grace", self.late_replies, self.late_grace.as_secs_f64()));
+        }
+        if counters.rewritten > 0 {
+            lines.push(format!("{} replies came back with a rewritten icmp id", counters.rewritten));
+        }
+        for (source, replies) in &counters.by_source {
+            lines.push(format!("{} replies from {}", replies, source));
+        }
+
+        // There's nothing to sum up without replies
+        let rtts = stats.rtts;
+        if rtts.count > 0 {
+            lines.push(format!("round-trip min/avg/max/stddev = {}/{}/{}/{} ms",
+                decimal(rtts.min, 3),
+                decimal(rtts.mean(), 3),
+                decimal(rtts.max, 3),
+                decimal(rtts.stddev(), 3),
+            ));
+        }
+        lines.extend(stats.extremes.summary(rtts));
+
+        if self.verbose {
+            lines.push(format!("{} icmp packets received, skipping {}", counters.received, counters.rejections));
+        }
+
+        lines
+    }
+
+    // Counts the late replies to lost probes that are still within the grace,
+    // and forgets the probes the grace has run out for
+    fn record_late(
+        &mut self,
+        out: &mut Output,
+        stats: &mut PingStats,
+        lost: &mut VecDeque<LostProbe>,
+        late: Vec<LateReply>,
+    ) -> io::Result<()> {
+        let window = self.timeout.saturating_add(self.late_grace);
+        let mut lines = Vec::new();
+
+        for reply in late {
+            // Anything else is a duplicate, or a reply to a probe that was
+            // answered in time
+            let probe = match lost.iter().position(|p| p.seq == reply.seq).and_then(|i| lost.remove(i)) {
+                Some(probe) => probe,
+                None => continue,
+            };
+            let rtt = reply.received_at.saturating_duration_since(probe.sent);
+            if rtt > window {
+                continue;
+            }
+
+            stats.num_received += 1;
+            stats.total_rtt += rtt.as_millis();
+            stats.extremes.late_reply(stats.rtts, rtt.as_secs_f64() * 1000.0, Moment { seq: probe.seq, at: probe.sent_at });
+            stats.rtts.record(rtt);
+            self.late_replies += 1;
+            lines.push(format!("Late reply for icmp_seq {}: {}ms rtt", probe.seq, rtt.as_millis()));
+        }
+
+        let now = Instant::now();
+        lost.retain(|p| now.saturating_duration_since(p.sent) <= window);
+
+        if self.quiet || self.rtt_only.is_some() {
+            return Ok(());
+        }
+        lines.into_iter().try_for_each(|line| out.result(line))
+    }
+
+    // Keeps listening after the last probe, for as long as the probes that
+    // timed out can still be answered
+    fn wait_out_grace(
+        &mut self,
+        out: &mut Output,
+        stats: &mut PingStats,
+        lost: &mut VecDeque<LostProbe>,
+        transport: &mut dyn Transport,
+        last_seq: u16,
+    ) -> io::Result<()> {
+        let window = self.timeout.saturating_add(self.late_grace);
+        while let Some(last) = lost.back() {
+            let remaining = window.saturating_sub(last.sent.elapsed());
+            if remaining == Duration::ZERO || interrupted() {
+                break;
+            }
+
+            // The transport is still waiting for the reply to the last probe
+            let reply = transport.next_reply(remaining)?;
+            let mut late = transport.take_late();
+            if let Reply::Echo = reply {
+                late.push(LateReply { seq: last_seq, received_at: Instant::now() });
+            }
+            self.record_late(out, stats, lost, late)?;
+        }
+        Ok(())
+    }
+
+    fn format_line(&self, stats: PingStats, probe: ProbeResult) -> String {
+        let mut line = Vec::new();
+        if self.show_send_time {
+            line.push(Segment::new(" ", format!("[{}]", unix_timestamp(probe.sent_at))));
+        }
+        if let (true, Some(error)) = (probe.success, probe.error) {
+            line.extend(stats.stats_for_hop(error, probe.rtt));
+        } else if probe.success {
+            line.extend(stats.stats_for_rtt(probe.rtt));
+        } else if let Some(error) = probe.error {
+            line.extend(stats.stats_for_error(error));
+        } else {
+            line.extend(stats.stats_for_timeout());
+        }
+        if let Some(interval) = probe.interval {
+            line.push(Segment::droppable(", ", format!("{}s interval", decimal(interval.as_secs_f64(), 3)), INTERVAL_DROP_RANK));
+        }
+        if let Some(timing) = probe.timing {
+            line.push(Segment::new(" ", timing.to_string()));
+        }
+        if self.local_target {
+            line.push(Segment::droppable(" ", format!("({})", LOCAL_NOTE), LOCAL_NOTE_DROP_RANK));
+        }
+        fit(&line, self.width)
+    }
+
+    fn write_line(
+        &mut self,
+        out: &mut Output,
+        stats: PingStats,
+        outages: &OutageTracker,
+        probe: ProbeResult,
+        gap: Option<String>,
+    ) -> io::Result<()> {
+        if self.quiet {
+            return Ok(());
+        }
+
+        if let Some(rtt_only) = &self.rtt_only {
+            // Always with a dot, since this is meant for other programs
+            return match (probe.success, &rtt_only.loss_value) {
+                (true, _) => out.result(format!("{:.3}", probe.elapsed.as_secs_f64() * 1000.0)),
+                (false, Some(loss_value)) => out.result(loss_value),
+                (false, None) => Ok(()),
+            };
+        }
+
+        let line = self.format_line(stats, probe);
+
+        if probe.success {
+            if let Some(streak) = self.coalescer.as_mut().and_then(Coalescer::end_streak) {
+                out.result(streak)?;
+            }
+            if let Some(gap) = gap {
+                out.result(gap)?;
+            }
+            return out.result(line);
+        }
+
+        let coalescer = match &mut self.coalescer {
+            Some(coalescer) => coalescer,
+            None => return out.result(line),
+        };
+
+        let now = Instant::now();
+        match coalescer.timeout(outages.consecutive_lost(), outages.outage(now), now) {
+            Action::Verbatim => out.result(line),
+            Action::Update(streak) => out.result_in_place(streak),
+            Action::Print(streak) => out.result(streak),
+            Action::Hide => Ok(()),
+        }
+    }
+
+    pub fn run(mut self, out: &mut Output, transport: &mut dyn Transport) -> io::Result<PingStats> {
+        let mut data = [0; PACKET_DATA_SIZE];
+        let mut stats = self.restore_state(out);
+        let restored = stats;
+        let mut last_saved = Instant::now();
+        let mut outages = OutageTracker::new(self.down_after);
+        let mut gaps = GapTracker::default();
+        let mut timing_report = TimingReport::default();
+        // When the next probe should go out, once there has been a wait
+        let mut planned_send = None;
+        let mut ramp_report = self.ramp.map(RampReport::new);
+        let mut warned_id_rewrite = false;
+        let mut lost = VecDeque::new();
+        let started = Instant::now();
+
+        let packets_to_send = self.packets_to_send;
+        let done = |stats: PingStats| packets_to_send.map(|c| stats.since(restored).num_sent >= c).unwrap_or(false);
+        let seq_start = self.seq_start;
+        let next_seq = |stats: PingStats| seq_start.wrapping_add(stats.since(restored).num_sent as u16);
+
+        // Errors end the loop rather than returning, so the state is still saved
+        let ended = loop {
+            if done(stats) || interrupted() {
+                break Ok(());
+            }
+
+            let seq = next_seq(stats);
+            let send_started = Instant::now();
+            let sent = transport.send_ping(&mut data, seq);
+            let timing = ProbeTiming {
+                sched_late: planned_send.map(|p| send_started.saturating_duration_since(p)).unwrap_or_default(),
+                send: send_started.elapsed(),
+            };
+
+            if let Err(e) = sent {
+                if let Some(first_result) = self.first_result.take() {
+                    first_result.write(seq, SystemTime::now(), Resolution::Error(e.to_string()), &[]);
+                }
+                break Err(e);
+            }
+
+            let time_sent = Instant::now();
+            let wall_time_sent = SystemTime::now();
+            let since_start = time_sent.duration_since(started);
+            stats.num_sent += 1;
+
+            let reply = match transport.next_reply(self.timeout) {
+                Ok(reply) => reply,
+                Err(e) => break Err(e),
+            };
+
+            let invalid = transport.take_invalid();
+            if self.show_invalid && !self.quiet && self.rtt_only.is_none() {
+                let shown = invalid.iter().try_for_each(|reply| {
+                    out.result(format!("invalid reply from {}: {}", reply.from, reply.reason))
+                });
+                if let Err(e) = shown {
+                    break Err(e);
+                }
+            }
+
+            let late = transport.take_late();
+            if let Err(e) = self.record_late(out, &mut stats, &mut lost, late) {
+                break Err(e);
+            }
+
+            if !warned_id_rewrite {
+                if let Some(rewrite) = transport.receive_counters().id_rewrite {
+                    warning(rewrite);
+                    warned_id_rewrite = true;
+                }
+            }
+
+            // The probe counts as sent, but nothing else is known about it
+            if interrupted() {
+                break Ok(());
+            }
+            let hop_response = match reply {
+                Reply::Error(error) => self.ttl_exceeded_ok && error.is_ttl_exceeded(),
+                _ => false,
+            };
+            let success = matches!(reply, Reply::Echo) || hop_response;
+            let error = match reply {
+                Reply::Error(error) => Some(error),
+                _ => None,
+            };
+            let elapsed = Instant::now().duration_since(time_sent);
+            let rtt = elapsed.as_millis();
+
+            if let Some(first_result) = self.first_result.take() {
+                let resolution = match reply {
+                    Reply::Echo => Resolution::Reply(rtt),
+                    Reply::Error(_) if hop_response => Resolution::Reply(rtt),
+                    Reply::Error(error) => Resolution::IcmpError(error),
+                    Reply::Timeout => Resolution::Timeout,
+                };
+                first_result.write(seq, wall_time_sent, resolution, &invalid);
+            }
+
+            let moment = Moment { seq, at: wall_time_sent };
+            if success {
+                stats.total_rtt += rtt;
+                stats.num_received += 1;
+                stats.extremes.reply(stats.rtts, elapsed.as_secs_f64() * 1000.0, moment, SystemTime::now());
+                stats.rtts.record(elapsed);
+            } else {
+                stats.extremes.lost(moment);
+            }
+            if matches!(reply, Reply::Timeout) && self.late_grace > Duration::ZERO {
+                lost.push_back(LostProbe { seq, sent: time_sent, sent_at: wall_time_sent });
+            }
+
+            if let Some(loss_map) = &mut self.loss_map {
+                loss_map.push(match reply {
+                    Reply::Echo => Outcome::Reply,
+                    Reply::Error(_) if hop_response => Outcome::Reply,
+                    Reply::Error(_) => Outcome::Error,
+                    Reply::Timeout => Outcome::Lost,
+                });
+            }
+
+            if let Some(ramp_report) = &mut ramp_report {
+                ramp_report.record(since_start, success);
+            }
+
+            let transition = outages.record(success);
+
+            let gap = if success {
+                gaps.end(time_sent)
+            } else {
+                gaps.lost(seq, time_sent);
+                None
+            };
+
+            let probe = ProbeResult {
+                success,
+                rtt,
+                elapsed,
+                error,
+                sent_at: wall_time_sent,
+                interval: self.ramp.map(|r| r.interval_at(since_start)),
+                timing: if self.timing_breakdown { Some(timing) } else { None },
+            };
+
+            // If the line can't be written, the bookkeeping for this probe
+            // is still finished before giving up
+            let print_started = Instant::now();
+            let written = self.write_line(out, stats, &outages, probe, gap);
+            timing_report.record(timing, print_started.elapsed());
+
+            if let Some(transition) = transition {
+                let target = &self.target;
+                self.hooks.trigger(transition, |outage| Event {
+                    target: target.clone(),
+                    seq,
+                    loss_percent: stats.total_percent_loss() * 100.0,
+                    outage,
+                });
+            }
+
+            if last_saved.elapsed() >= STATE_SAVE_INTERVAL {
+                self.save_state(out, stats, next_seq(stats), started.elapsed());
+                last_saved = Instant::now();
+            }
+
+            if let Err(e) = written {
+                break Err(e);
+            }
+
+            // There's no need to wait after the last probe
+            if done(stats) {
+                break Ok(());
+            }
+
+            let interval = probe.interval.unwrap_or(self.interval);
+            planned_send = Instant::now().checked_add(interval);
+            sleep(interval);
+        };
+
+        let last_seq = next_seq(stats).wrapping_sub(1);
+        let ended = ended.and_then(|()| self.wait_out_grace(out, &mut stats, &mut lost, transport, last_seq));
+
+        // An outage still going on lasted at least until now
+        stats.extremes.end_outage(SystemTime::now());
+        self.save_state(out, stats, next_seq(stats), started.elapsed());
+        ended?;
+
+        if self.quiet {
+            return Ok(stats.since(restored));
+        }
+
+        // With --rtt-only, stdout is kept for the rtts
+        if self.rtt_only.is_some() {
+            for line in self.summary(stats, self.restored_elapsed + started.elapsed(), &transport.receive_counters()) {
+                out.status(line);
+            }
+            return Ok(stats.since(restored));
+        }
+
+        // So the streak's line isn't left showing fewer timeouts than there were
+        if let Some(streak) = self.coalescer.as_mut().and_then(Coalescer::end_streak) {
+            out.result(streak)?;
+        }
+
+        // A gap still going when the run ends is described too
+        if let Some(gap) = gaps.end(Instant::now()) {
+            out.result(gap)?;
+        }
+
+        if let Some(loss_map) = &self.loss_map {
+            out.result("")?;
+            out.result("Loss map ('.' reply, 'x' lost, 'e' icmp error):")?;
+            out.result(loss_map)?;
+        }
+
+        if let Some(ramp_report) = &ramp_report {
+            out.result("")?;
+            out.result(ramp_report)?;
+        }
+
+        if self.timing_breakdown {
+            out.result("")?;
+            out.result(timing_report)?;
+        }
+
+        out.result("")?;
+        for line in self.summary(stats, self.restored_elapsed + started.elapsed(), &transport.receive_counters()) {
+            out.result(line)?;
+        }
+
+        Ok(stats.since(restored))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::net::{IpAddr, Ipv4Addr};
+    use crate::output::Buffer;
+    use crate::ping::{IdRewrite, InvalidReason, InvalidReply};
+
+    // Answers each probe with the next scripted reply, right away
+    #[derive(Default)]
+    struct MockTransport {
+        replies: VecDeque<Reply>,
+        sent: Vec<u16>,
+        counters: ReceiveCounters,
+        // Handed over along with the next reply
+        invalid: Vec<InvalidReply>,
+        // The sequence numbers of late replies, handed over a batch at a
+        // time along with each reply
+        late: VecDeque<Vec<u16>>,
+        late_now: Vec<LateReply>,
+    }
+
+    impl MockTransport {
+        fn new(replies: impl IntoIterator<Item = Reply>) -> MockTransport {
+            MockTransport { replies: replies.into_iter().collect(), ..MockTransport::default() }
+        }
+    }
+
+    impl Transport for MockTransport {
+        fn send_ping(&mut self, data: &mut [u8], seq: u16) -> io::Result<usize> {
+            self.sent.push(seq);
+            Ok(data.len())
+        }
+
+        fn next_reply(&mut self, _timeout: Duration) -> io::Result<Reply> {
+            let received_at = Instant::now();
+            let late = self.late.pop_front().unwrap_or_default();
+            self.late_now = late.into_iter().map(|seq| LateReply { seq, received_at }).collect();
+            Ok(self.replies.pop_front().unwrap_or(Reply::Timeout))
+        }
+
+        fn receive_counters(&self) -> ReceiveCounters {
+            self.counters.clone()
+        }
+
+        fn take_invalid(&mut self) -> Vec<InvalidReply> {
+            std::mem::take(&mut self.invalid)
+        }
+
+        fn take_late(&mut self) -> Vec<LateReply> {
+            std::mem::take(&mut self.late_now)
+        }
+    }
+
+    // Runs `pinger` against `transport` without waiting between probes,
+    // returning what it wrote to stdout
+    fn run(pinger: Pinger, transport: &mut MockTransport) -> (PingStats, String) {
+        let buffer = Buffer::default();
+        let stats = pinger.interval(Duration::ZERO).run(&mut Output::to_buffer(buffer.clone()), transport).unwrap();
+        (stats, buffer.contents())
+    }
+
+    fn stats(num_sent: u64, rtts_ms: &[u128]) -> PingStats {
+        PingStats {
+            num_sent,
+            num_received: rtts_ms.len() as u64,
+            total_rtt: rtts_ms.iter().sum(),
+            ..PingStats::default()
+        }
+    }
+
+    fn probe(rtt_ms: Option<u128>) -> ProbeResult {
+        ProbeResult {
+            success: rtt_ms.is_some(),
+            rtt: rtt_ms.unwrap_or_default(),
+            elapsed: Duration::from_millis(rtt_ms.unwrap_or_default() as u64),
+            error: None,
+            sent_at: SystemTime::UNIX_EPOCH,
+            interval: None,
+            timing: None,
+        }
+    }
+
+    #[test]
+    fn fits_probe_lines_to_the_width() {
+        let stats = stats(4, &[10, 12, 11]);
+        let reply = probe(Some(12));
+        let goldens = [
+            (None, "Response received: 12ms rtt, 11 average rtt, 1/4 lost (25.00%) (local address — replies do not traverse the network)"),
+            (Some(80), "Response received: 12ms rtt, 11 average rtt, 1/4 lost (25.00%) …"),
+            (Some(64), "Response received: 12ms rtt, 11 average rtt, 1/4 lost (25.00%) …"),
+            (Some(63), "Response received: 12ms rtt, …, 1/4 lost (25.00%) …"),
+            // The rtt and the loss are kept, even if they don't fit
+            (Some(20), "Response received: 12ms rtt, …, 1/4 lost (25.00%) …"),
+        ];
+        for (width, golden) in &goldens {
+            let pinger = Pinger::new("gw").local_target(true).width(*width);
+            assert_eq!(pinger.format_line(stats, reply), *golden, "at {:?} columns", width);
+        }
+    }
+
+    #[test]
+    fn fits_timeouts_and_errors_too() {
+        let stats = stats(4, &[10, 12, 11]);
+        let pinger = Pinger::new("gw").width(Some(40));
+        assert_eq!(pinger.format_line(stats, probe(None)), "Response timed out: …, 1/4 lost (25.00%)");
+
+        let error = IcmpError { from: IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)), icmp_type: 11, code: 0 };
+        let probe = ProbeResult { error: Some(error), ..probe(None) };
+        assert_eq!(
+            Pinger::new("gw").format_line(stats, probe),
+            "Time to live exceeded from 192.0.2.1: 11 average rtt, 1/4 lost (25.00%)",
+        );
+        assert_eq!(
+            Pinger::new("gw").width(Some(40)).format_line(stats, probe),
+            "Time to live exceeded from 192.0.2.1: …, 1/4 lost (25.00%)",
+        );
+    }
+
+    #[test]
+    fn counts_the_ttl_running_out_as_a_response_only_when_asked() {
+        let hop = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        let ttl_exceeded = Reply::Error(IcmpError { from: hop, icmp_type: 11, code: 0 });
+        let unreachable = Reply::Error(IcmpError { from: hop, icmp_type: 3, code: 1 });
+        let replies = || vec![ttl_exceeded, Reply::Echo, unreachable];
+
+        let (stats, output) = run(Pinger::new("gw").count(3), &mut MockTransport::new(replies()));
+        assert_eq!(stats.num_received, 1);
+        assert!(output.contains("Time to live exceeded from 192.0.2.1:"), "{}", output);
+
+        let (stats, output) = run(Pinger::new("gw").count(3).ttl_exceeded_ok(true), &mut MockTransport::new(replies()));
+        assert_eq!(stats.num_received, 2);
+        assert_eq!(stats.rtts.count, 2);
+        assert!(output.contains("Hop response from 192.0.2.1: 0ms rtt,"), "{}", output);
+        assert!(!output.contains("Time to live exceeded"), "{}", output);
+        // Other errors are still errors
+        assert!(output.contains("Destination host unreachable from 192.0.2.1:"), "{}", output);
+    }
+
+    #[test]
+    fn describes_a_gap_still_going_when_the_run_ends() {
+        let replies = vec![Reply::Echo, Reply::Timeout, Reply::Timeout];
+        let (_, output) = run(Pinger::new("gw").count(3).timeout(Duration::ZERO), &mut MockTransport::new(replies));
+        // Before the summary, after the probes' lines
+        let gap = output.find("--- gap: icmp_seq 1–2 lost (2 probes, ").expect(&output);
+        assert!(gap > output.rfind("Response timed out").unwrap(), "{}", output);
+        assert!(gap < output.find("--- gw ping statistics ---").unwrap(), "{}", output);
+    }
+
+    #[test]
+    fn shows_the_whole_streak_when_the_run_ends_during_it() {
+        let replies = vec![Reply::Echo, Reply::Timeout, Reply::Timeout, Reply::Timeout, Reply::Timeout, Reply::Timeout];
+        let pinger = Pinger::new("gw")
+            .count(6)
+            .coalesce_timeouts(Coalescer::new(3, Duration::from_secs(10), false));
+        let (stats, output) = run(pinger, &mut MockTransport::new(replies));
+
+        assert_eq!((stats.num_sent, stats.num_received), (6, 1));
+        let streaks = output.lines().filter(|l| l.contains("consecutive")).collect::<Vec<_>>();
+        assert_eq!(streaks, vec![
+            "... 4 consecutive timeouts (last 0.0 s) ...",
+            "... 5 consecutive timeouts (last 0.0 s) ...",
+        ]);
+        // And before the summary
+        assert!(output.find("5 consecutive").unwrap() < output.find("ping statistics").unwrap());
+    }
+
+    #[test]
+    fn waits_three_times_the_expected_rtt() {
+        assert_eq!(timeout_for_rtt(Duration::from_millis(600)), Duration::from_millis(1800));
+        assert_eq!(timeout_for_rtt(Duration::from_millis(0)), Duration::from_millis(0));
+        // The largest --rtt-expected there can be
+        assert_eq!(timeout_for_rtt(Duration::from_millis(u64::MAX)), Duration::from_millis(u64::MAX) * 3);
+    }
+
+    #[test]
+    fn gives_late_replies_twice_the_timeout() {
+        assert_eq!(grace_for_timeout(Duration::from_millis(1800)), Duration::from_millis(3600));
+        assert_eq!(grace_for_timeout(Duration::ZERO), Duration::ZERO);
+        // The largest --wait there can be
+        assert_eq!(grace_for_timeout(Duration::MAX), Duration::MAX);
+    }
+
+    // A run where probe 0 times out and its reply comes in while probe 1 is
+    // being waited for, along with a duplicate and a reply for probe 1
+    fn late_run(pinger: Pinger) -> (PingStats, String) {
+        let mut transport = MockTransport::new(vec![Reply::Timeout, Reply::Echo]);
+        transport.late = vec![vec![], vec![0, 0, 1]].into();
+        run(pinger.count(2).timeout(Duration::ZERO), &mut transport)
+    }
+
+    #[test]
+    fn counts_late_replies_within_the_grace() {
+        let (stats, output) = late_run(Pinger::new("gw").late_grace(Duration::from_secs(60)));
+        assert_eq!((stats.num_sent, stats.num_received, stats.rtts.count), (2, 2, 2));
+        assert_eq!(output.matches("Late reply for icmp_seq 0: 0ms rtt").count(), 1, "{}", output);
+        assert!(output.contains("2 packets transmitted, 2 received, 0.00% packet loss"), "{}", output);
+        assert!(output.contains("1 replies came in late, within the 60s grace"), "{}", output);
+        // The probe's own line still says it was lost
+        assert!(output.contains("Response timed out"), "{}", output);
+
+        // Without a grace, or past it, they're just skipped
+        let (stats, output) = late_run(Pinger::new("gw"));
+        assert_eq!(stats.num_received, 1);
+        assert!(!output.contains("late"), "{}", output);
+        let (stats, output) = late_run(Pinger::new("gw").late_grace(Duration::from_nanos(1)));
+        assert_eq!(stats.num_received, 1);
+        assert!(!output.contains("late"), "{}", output);
+    }
+
+    #[test]
+    fn waits_out_the_grace_after_the_last_probe() {
+        let mut transport = MockTransport::new(vec![Reply::Timeout, Reply::Echo]);
+        let pinger = Pinger::new("gw").count(1).timeout(Duration::ZERO).late_grace(Duration::from_secs(60));
+        let (stats, output) = run(pinger, &mut transport);
+        assert_eq!((stats.num_sent, stats.num_received), (1, 1));
+        assert!(output.contains("Late reply for icmp_seq 0:"), "{}", output);
+    }
+
+    // Each line of `output`, with the rtts (which are measured, so can't be
+    // known ahead) replaced by `rtt`
+    fn rtt_lines(output: &str) -> Vec<String> {
+        output.lines()
+            .map(|l| match l.split_once('.') {
+                Some((ms, micros)) if ms.parse::<u64>().is_ok() && micros.len() == 3 && micros.parse::<u32>().is_ok() => "rtt".to_owned(),
+                _ => l.to_owned(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn prints_only_rtts_and_the_loss_value() {
+        let replies = vec![Reply::Echo, Reply::Timeout, Reply::Echo];
+        let pinger = Pinger::new("gw").count(3).rtt_only(RttOnly { loss_value: Some("-1".to_owned()) });
+        let (_, output) = run(pinger, &mut MockTransport::new(replies));
+        assert_eq!(rtt_lines(&output), vec!["rtt", "-1", "rtt"]);
+    }
+
+    #[test]
+    fn prints_only_rtts_when_skipping_losses() {
+        let replies = vec![Reply::Timeout, Reply::Echo, Reply::Timeout, Reply::Echo];
+        let pinger = Pinger::new("gw").count(4).rtt_only(RttOnly { loss_value: None });
+        let (_, output) = run(pinger, &mut MockTransport::new(replies));
+        assert_eq!(rtt_lines(&output), vec!["rtt", "rtt"]);
+    }
+
+    #[test]
+    fn sums_up_extreme_runs() {
+        let stats = PingStats { num_sent: u64::MAX, num_received: u64::MAX - 1, total_rtt: u128::MAX, ..PingStats::default() };
+        assert_eq!(Pinger::new("gw").summary(stats, Duration::MAX, &ReceiveCounters::default()), vec![
+            "--- gw ping statistics ---".to_owned(),
+            format!("{} packets transmitted, {} received, 0.00% packet loss, time {}ms", u64::MAX, u64::MAX - 1, Duration::MAX.as_millis()),
+        ]);
+        assert_eq!(stats.total_lost(), 1);
+        assert_eq!(stats.since(stats).num_sent, 0);
+
+        let nothing = PingStats::default();
+        assert_eq!(Pinger::new("gw").summary(nothing, Duration::ZERO, &ReceiveCounters::default())[1], "0 packets transmitted, 0 received, 0.00% packet loss, time 0ms");
+        assert_eq!(nothing.avg_rtt(), 0);
+    }
+
+    #[test]
+    fn counts_replies_with_a_rewritten_id() {
+        let mut transport = MockTransport::new(vec![Reply::Echo, Reply::Echo]);
+        let (_, output) = run(Pinger::new("gw").count(2), &mut transport);
+        assert!(!output.contains("rewritten"));
+
+        transport.counters = ReceiveCounters {
+            id_rewrite: Some(IdRewrite { expected: 0x1a2b, got: 0x0000 }),
+            rewritten: 2,
+            ..ReceiveCounters::default()
+        };
+        transport.replies.extend(vec![Reply::Echo, Reply::Echo]);
+        let (_, output) = run(Pinger::new("gw").count(2), &mut transport);
+        let summary = output.lines().skip_while(|l| !l.contains("ping statistics")).collect::<Vec<_>>();
+        assert_eq!(summary[2], "2 replies came back with a rewritten icmp id");
+    }
+
+    #[test]
+    fn counts_replies_by_source() {
+        let mut transport = MockTransport::new(vec![Reply::Echo, Reply::Echo, Reply::Echo]);
+        transport.counters.by_source.insert(IpAddr::V4(Ipv4Addr::new(198, 51, 100, 9)), 2);
+        transport.counters.by_source.insert(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 7)), 1);
+        let (_, output) = run(Pinger::new("gw").count(3), &mut transport);
+        let summary = output.lines().skip_while(|l| !l.contains("ping statistics")).collect::<Vec<_>>();
+        assert_eq!(summary[2..4], ["1 replies from 192.0.2.7", "2 replies from 198.51.100.9"]);
+    }
+
+    #[test]
+    fn sums_up_what_was_received_when_verbose() {
+        let mut transport = MockTransport::new(vec![Reply::Echo]);
+        transport.counters.received = 40;
+        transport.counters.rejections.wrong_type = 39;
+        let (_, output) = run(Pinger::new("gw").count(1), &mut transport);
+        assert!(!output.contains("icmp packets received"));
+
+        transport.replies.push_back(Reply::Echo);
+        let (_, output) = run(Pinger::new("gw").count(1).verbose(true), &mut transport);
+        assert_eq!(
+            output.lines().last().unwrap(),
+            "40 icmp packets received, skipping 39 of another type, 0 for another ping, 0 from another source, 0 for an earlier probe, 0 too short to read",
+        );
+    }
+
+    #[test]
+    fn shows_invalid_replies_when_asked() {
+        let checksum = InvalidReply { from: IpAddr::V4(Ipv4Addr::new(10, 0, 0, 9)), reason: InvalidReason::BadChecksum };
+        let mut transport = MockTransport::new(vec![Reply::Echo]);
+        transport.invalid = vec![checksum];
+        transport.counters.invalid = 1;
+        let (_, output) = run(Pinger::new("gw").count(1), &mut transport);
+        assert!(!output.contains("invalid reply"));
+        assert!(output.contains("1 packets transmitted, 1 received, 1 invalid, 0.00% packet loss"), "{}", output);
+
+        transport.replies.push_back(Reply::Timeout);
+        transport.invalid = vec![checksum, InvalidReply { reason: InvalidReason::StaleTimestamp, ..checksum }];
+        let (_, output) = run(Pinger::new("gw").count(1).show_invalid(true), &mut transport);
+        assert_eq!(output.lines().take(3).collect::<Vec<_>>(), vec![
+            "invalid reply from 10.0.0.9: checksum mismatch",
+            "invalid reply from 10.0.0.9: stale timestamp",
+            "Response timed out: 0 average rtt, 1/1 lost (100.00%)",
+        ]);
+
+        // And nothing's said about them when there are none
+        let (_, output) = run(Pinger::new("gw").count(1), &mut MockTransport::new(vec![Reply::Echo]));
+        assert!(output.contains("1 packets transmitted, 1 received, 0.00% packet loss"), "{}", output);
+    }
+
+    #[test]
+    fn says_where_the_extremes_were() {
+        let replies = vec![Reply::Echo, Reply::Timeout, Reply::Timeout, Reply::Echo, Reply::Timeout];
+        let (stats, output) = run(Pinger::new("gw").count(5).seq_start(10), &mut MockTransport::new(replies));
+
+        // The second outage is still going on when the run ends
+        let (started, _) = stats.extremes.longest_outage.unwrap();
+        assert!(started.seq == 11 || started.seq == 14, "{:?}", started);
+        assert!([10, 13].contains(&stats.extremes.fastest.unwrap().seq));
+        assert!([10, 13].contains(&stats.extremes.slowest.unwrap().seq));
+
+        let summary = output.lines().skip_while(|l| !l.starts_with("round-trip")).skip(1).collect::<Vec<_>>();
+        assert_eq!(summary.len(), 3, "{}", output);
+        assert!(summary[0].starts_with("min "), "{}", output);
+        assert!(summary[1].starts_with("max "), "{}", output);
+        assert!(summary[2].starts_with("longest outage "), "{}", output);
+        assert!(summary[2].contains(&format!(" from seq {}, ", started.seq)), "{}", output);
+    }
+
+    #[test]
+    fn numbers_probes_across_the_wraparound() {
+        let pinger = Pinger::new("gw").count(4).seq_start(65534);
+        let mut transport = MockTransport::new(vec![]);
+        run(pinger, &mut transport);
+        assert_eq!(transport.sent, vec![65534, 65535, 0, 1]);
+    }
+
+    #[test]
+    fn breaks_down_the_timing_of_each_probe() {
+        let pinger = Pinger::new("gw").count(2).timing_breakdown(true);
+        let (_, output) = run(pinger, &mut MockTransport::new(vec![Reply::Echo, Reply::Timeout]));
+
+        let probes = output.lines().filter(|l| l.starts_with("Response")).collect::<Vec<_>>();
+        assert_eq!(probes.len(), 2);
+        assert!(probes.iter().all(|l| l.contains(" [sched+") && l.ends_with(" ms]")), "{:?}", probes);
+        assert!(output.contains("Time spent in ping (mean/max ms): sched+"));
+    }
+
+    // The threads of this process called `name`
+    #[cfg(target_os = "linux")]
+    fn threads_named(name: &str) -> usize {
+        std::fs::read_dir("/proc/self/task").unwrap()
+            .filter_map(|task| std::fs::read_to_string(task.ok()?.path().join("comm")).ok())
+            .filter(|comm| comm.trim_end() == name)
+            .count()
+    }
+
+    // The open file descriptors of this process for files in `dir`
+    #[cfg(target_os = "linux")]
+    fn fds_in(dir: &std::path::Path) -> usize {
+        std::fs::read_dir("/proc/self/fd").unwrap()
+            .filter_map(|fd| std::fs::read_link(fd.ok()?.path()).ok())
+            .filter(|target| target.starts_with(dir))
+            .count()
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn leaves_nothing_behind_once_dropped() {
+        use crate::first_result::Destination;
+        use crate::hooks::{Hook, HookConfig};
+
+        // Names no other test uses, so tests running alongside don't count
+        const HOOK: &str = "teardown-hook";
+        let dir = std::env::temp_dir().join(format!("ping-teardown-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        for _ in 0..10 {
+            // Hooks that are still running when the run ends
+            let config = HookConfig { shell: false, timeout: Duration::from_millis(100) };
+            let hooks = Hooks {
+                on_down: Some(Hook::spawn(HOOK, "sleep 5".to_owned(), config.clone()).unwrap()),
+                on_up: Some(Hook::spawn(HOOK, "sleep 5".to_owned(), config).unwrap()),
+            };
+            let pinger = Pinger::new("gw")
+                .count(4)
+                .down_after(1)
+                .hooks(hooks)
+                .loss_map(LossMap::new(10))
+                .coalesce_timeouts(Coalescer::new(1, Duration::from_secs(10), false))
+                .state_file(StateFile::new(dir.join("state")))
+                .first_result(FirstResult::new("gw", Destination::File(dir.join("first-result"))));
+            // A new thread only names itself once it starts running
+            let spawned = Instant::now();
+            while threads_named(HOOK) < 2 && spawned.elapsed() < Duration::from_secs(1) {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            assert_eq!(threads_named(HOOK), 2);
+
+            let started = Instant::now();
+            run(pinger, &mut MockTransport::new(vec![Reply::Timeout, Reply::Echo, Reply::Timeout, Reply::Echo]));
+
+            // Waiting for the hooks is bounded by their timeout
+            assert!(started.elapsed() < Duration::from_secs(2));
+            assert_eq!(threads_named(HOOK), 0);
+            assert_eq!(fds_in(&dir), 0);
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,191 @@
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+use crate::output::decimal;
+use crate::parse_duration;
+
+// Probes can't be sent more often than this without root, like iputils
+pub const MIN_UNPRIVILEGED_INTERVAL: Duration = Duration::from_millis(200);
+
+const BUCKETS: usize = 10;
+
+// An interval that changes linearly from `start` to `end` over `over`, and
+// then stays at `end`
+#[derive(Clone, Copy, Debug)]
+pub struct Ramp {
+    start: Duration,
+    end: Duration,
+    over: Duration,
+    floor: Duration,
+}
+
+impl Ramp {
+    // Parses `START:END:DURATION`, each of which is in seconds or has an
+    // 's' or 'ms' suffix
+    pub fn parse(s: &str) -> io::Result<Ramp> {
+        let parts = s.split(':').map(parse_duration).collect::<Option<Vec<_>>>();
+
+        match parts.as_deref() {
+            Some(&[start, end, over]) if over > Duration::from_secs(0) => {
+                Ok(Ramp { start, end, over, floor: Duration::from_secs(0) })
+            },
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The value for the 'interval_ramp' flag must look like START:END:DURATION, like 1:0.01:300.",
+            )),
+        }
+    }
+
+    // Intervals below the floor are raised to it
+    pub fn with_floor(self, floor: Duration) -> Ramp {
+        Ramp { floor, ..self }
+    }
+
+    pub fn reaches_below(self, interval: Duration) -> bool {
+        self.start.min(self.end) < interval
+    }
+
+    // The shortest interval the ramp ever uses
+    pub fn shortest(self) -> Duration {
+        self.start.min(self.end).max(self.floor)
+    }
+
+    fn progress(self, elapsed: Duration) -> f64 {
+        (elapsed.as_secs_f64() / self.over.as_secs_f64()).min(1.0)
+    }
+
+    pub fn interval_at(self, elapsed: Duration) -> Duration {
+        let start = self.start.as_secs_f64();
+        let end = self.end.as_secs_f64();
+        let interval = start + (end - start) * self.progress(elapsed);
+
+        Duration::from_secs_f64(interval).max(self.floor)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Bucket {
+    sent: u64,
+    lost: u64,
+}
+
+// Loss over each tenth of the ramp, so the rate where loss starts stands out.
+// Probes sent after the ramp is over count towards the last bucket.
+pub struct RampReport {
+    ramp: Ramp,
+    buckets: [Bucket; BUCKETS],
+}
+
+impl RampReport {
+    pub fn new(ramp: Ramp) -> RampReport {
+        RampReport {
+            ramp,
+            buckets: [Bucket::default(); BUCKETS],
+        }
+    }
+
+    pub fn record(&mut self, elapsed: Duration, success: bool) {
+        let i = ((self.ramp.progress(elapsed) * BUCKETS as f64) as usize).min(BUCKETS - 1);
+        self.buckets[i].sent += 1;
+        if !success {
+            self.buckets[i].lost += 1;
+        }
+    }
+}
+
+impl fmt::Display for RampReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Loss by probe interval:")?;
+
+        for (i, bucket) in self.buckets.iter().enumerate() {
+            if bucket.sent == 0 {
+                continue;
+            }
+
+            let from = self.ramp.over.mul_f64(i as f64 / BUCKETS as f64);
+            let to = self.ramp.over.mul_f64((i + 1) as f64 / BUCKETS as f64);
+            write!(f, "\n  {}-{} s: {}/{} lost ({}%)",
+                decimal(self.ramp.interval_at(from).as_secs_f64(), 3),
+                decimal(self.ramp.interval_at(to).as_secs_f64(), 3),
+                bucket.lost,
+                bucket.sent,
+                decimal(bucket.lost as f64 / bucket.sent as f64 * 100.0, 2),
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(s: f64) -> Duration {
+        Duration::from_secs_f64(s)
+    }
+
+    // Equal to the millisecond, since the interpolation goes through floats
+    fn assert_close(a: Duration, b: Duration) {
+        assert!((a.as_secs_f64() - b.as_secs_f64()).abs() < 0.001, "{:?} isn't {:?}", a, b);
+    }
+
+    #[test]
+    fn parses_start_end_and_duration() {
+        let ramp = Ramp::parse("1:10ms:300").unwrap();
+        assert_eq!((ramp.start, ramp.end, ramp.over), (secs(1.0), secs(0.01), secs(300.0)));
+
+        for s in &["", "1", "1:0.5", "1:0.5:0", "1:0.5:-3", "1:0.5:3:4", "1:10ms:5m", "a:b:c"] {
+            assert!(Ramp::parse(s).is_err(), "'{}' was accepted", s);
+        }
+    }
+
+    #[test]
+    fn interpolates_linearly() {
+        let ramp = Ramp::parse("1:0.01:300").unwrap();
+        assert_close(ramp.interval_at(secs(0.0)), secs(1.0));
+        assert_close(ramp.interval_at(secs(150.0)), secs(0.505));
+        assert_close(ramp.interval_at(secs(300.0)), secs(0.01));
+        // And stays at the end afterwards
+        assert_close(ramp.interval_at(secs(3000.0)), secs(0.01));
+
+        // Ramping up works the same way
+        let ramp = Ramp::parse("0.1:1.1:10").unwrap();
+        assert_close(ramp.interval_at(secs(2.5)), secs(0.35));
+    }
+
+    #[test]
+    fn never_goes_below_the_floor() {
+        let ramp = Ramp::parse("1:0.01:300").unwrap();
+        assert!(ramp.reaches_below(MIN_UNPRIVILEGED_INTERVAL));
+
+        let ramp = ramp.with_floor(MIN_UNPRIVILEGED_INTERVAL);
+        assert_close(ramp.interval_at(secs(0.0)), secs(1.0));
+        for elapsed in 0..=400 {
+            assert!(ramp.interval_at(secs(elapsed as f64)) >= MIN_UNPRIVILEGED_INTERVAL);
+        }
+        assert_eq!(ramp.shortest(), MIN_UNPRIVILEGED_INTERVAL);
+        assert!(!Ramp::parse("1:0.5:10").unwrap().reaches_below(MIN_UNPRIVILEGED_INTERVAL));
+    }
+
+    #[test]
+    fn reports_loss_by_tenth_of_the_ramp() {
+        let ramp = Ramp::parse("1:0:10").unwrap();
+        let mut report = RampReport::new(ramp);
+        report.record(secs(0.0), true);
+        report.record(secs(0.9), true);
+        report.record(secs(5.0), false);
+        report.record(secs(5.5), true);
+        // After the ramp, so in the last tenth
+        report.record(secs(9.5), false);
+        report.record(secs(60.0), false);
+
+        assert_eq!(report.to_string(), concat!(
+            "Loss by probe interval:\n",
+            "  1.000-0.900 s: 0/2 lost (0.00%)\n",
+            "  0.500-0.400 s: 1/2 lost (50.00%)\n",
+            "  0.100-0.000 s: 2/2 lost (100.00%)",
+        ));
+    }
+}
@@ -0,0 +1,160 @@
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scope {
+    Loopback,
+    // 169.254.0.0/16 and fe80::/10
+    LinkLocal,
+    // RFC 1918 addresses, unique local (fc00::/7) addresses, and the site
+    // local ones (fec0::/10) they replaced
+    Private,
+    Global,
+}
+
+impl Scope {
+    pub fn of(addr: IpAddr) -> Scope {
+        match addr {
+            IpAddr::V4(addr) => Scope::of_v4(addr),
+            IpAddr::V6(addr) => Scope::of_v6(addr),
+        }
+    }
+
+    fn of_v4(addr: Ipv4Addr) -> Scope {
+        if addr.is_loopback() {
+            Scope::Loopback
+        } else if addr.is_link_local() {
+            Scope::LinkLocal
+        } else if addr.is_private() {
+            Scope::Private
+        } else {
+            Scope::Global
+        }
+    }
+
+    fn of_v6(addr: Ipv6Addr) -> Scope {
+        let first_segment = addr.segments()[0];
+
+        // A v4-mapped address is in the scope of the v4 address it maps
+        if let Some(v4) = addr.to_ipv4_mapped() {
+            Scope::of_v4(v4)
+        } else if addr.is_loopback() {
+            Scope::Loopback
+        } else if first_segment & 0xffc0 == 0xfe80 {
+            Scope::LinkLocal
+        } else if first_segment & 0xfe00 == 0xfc00 || first_segment & 0xffc0 == 0xfec0 {
+            Scope::Private
+        } else {
+            Scope::Global
+        }
+    }
+}
+
+impl FromStr for Scope {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Scope> {
+        match s {
+            "private" => Ok(Scope::Private),
+            "global" => Ok(Scope::Global),
+            "link-local" => Ok(Scope::LinkLocal),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The value for the 'prefer_scope' flag must be one of private, global, or link-local.",
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Scope::Loopback => "loopback",
+            Scope::LinkLocal => "link-local",
+            Scope::Private => "private",
+            Scope::Global => "global",
+        })
+    }
+}
+
+// Moves the addresses in `preferred` to the front, keeping the resolver's
+// order otherwise
+pub fn prefer(addrs: &mut [IpAddr], preferred: Scope) {
+    addrs.sort_by_key(|a| Scope::of(*a) != preferred);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn finds_the_scope_of_each_address() {
+        let table = [
+            ("127.0.0.1", Scope::Loopback),
+            ("127.255.0.9", Scope::Loopback),
+            ("::1", Scope::Loopback),
+            ("169.254.0.1", Scope::LinkLocal),
+            ("169.254.255.255", Scope::LinkLocal),
+            ("fe80::1", Scope::LinkLocal),
+            ("febf:ffff::1", Scope::LinkLocal),
+            ("10.0.0.1", Scope::Private),
+            ("172.16.0.1", Scope::Private),
+            ("172.31.255.255", Scope::Private),
+            ("192.168.1.1", Scope::Private),
+            ("fc00::1", Scope::Private),
+            ("fd12:3456::1", Scope::Private),
+            ("fec0::1", Scope::Private),
+            ("feff::1", Scope::Private),
+            ("8.8.8.8", Scope::Global),
+            ("172.32.0.1", Scope::Global),
+            ("169.253.0.1", Scope::Global),
+            ("2001:db8::1", Scope::Global),
+            ("2606:4700::1111", Scope::Global),
+            ("fbff::1", Scope::Global),
+            ("fe7f::1", Scope::Global),
+            // v4-mapped addresses are in the scope of the address they map
+            ("::ffff:127.0.0.1", Scope::Loopback),
+            ("::ffff:169.254.0.1", Scope::LinkLocal),
+            ("::ffff:10.0.0.1", Scope::Private),
+            ("::ffff:8.8.8.8", Scope::Global),
+        ];
+        for (s, scope) in &table {
+            assert_eq!(Scope::of(addr(s)), *scope, "{}", s);
+        }
+    }
+
+    #[test]
+    fn prefers_the_scope_keeping_the_order_otherwise() {
+        let resolved = ["2001:db8::1", "10.0.0.1", "fe80::1", "8.8.8.8", "fd00::1", "169.254.0.1"];
+        let table: [(Scope, [&str; 6]); 3] = [
+            (Scope::Private, ["10.0.0.1", "fd00::1", "2001:db8::1", "fe80::1", "8.8.8.8", "169.254.0.1"]),
+            (Scope::Global, ["2001:db8::1", "8.8.8.8", "10.0.0.1", "fe80::1", "fd00::1", "169.254.0.1"]),
+            (Scope::LinkLocal, ["fe80::1", "169.254.0.1", "2001:db8::1", "10.0.0.1", "8.8.8.8", "fd00::1"]),
+        ];
+        for (scope, expected) in &table {
+            let mut addrs = resolved.iter().map(|s| addr(s)).collect::<Vec<_>>();
+            prefer(&mut addrs, *scope);
+            assert_eq!(addrs, expected.iter().map(|s| addr(s)).collect::<Vec<_>>(), "preferring {}", scope);
+        }
+
+        // Nothing in the scope leaves the order alone
+        let mut addrs = vec![addr("8.8.8.8"), addr("2001:db8::1")];
+        prefer(&mut addrs, Scope::Private);
+        assert_eq!(addrs, vec![addr("8.8.8.8"), addr("2001:db8::1")]);
+    }
+
+    #[test]
+    fn parses_the_scopes_that_can_be_preferred() {
+        assert_eq!("private".parse::<Scope>().unwrap(), Scope::Private);
+        assert_eq!("global".parse::<Scope>().unwrap(), Scope::Global);
+        assert_eq!("link-local".parse::<Scope>().unwrap(), Scope::LinkLocal);
+        assert!("loopback".parse::<Scope>().is_err());
+        assert_eq!(Scope::LinkLocal.to_string(), "link-local");
+    }
+}
@@ -0,0 +1,370 @@
+use std::fs;
+use std::io;
+use std::mem;
+use std::time::{Duration, Instant};
+
+use crate::interrupt::sleep;
+use crate::ping::{LateReply, Reply, Transport};
+
+pub const DEFAULT_SEED: u64 = 0x5eed_1e55_c0ff_ee00;
+
+const BASE_RTT_MS: f64 = 20.0;
+
+// Chances of entering and leaving a burst of loss in the `bursty` profile
+const BURST_START_CHANCE: f64 = 0.05;
+const BURST_END_CHANCE: f64 = 0.3;
+
+// The `flapping` profile is up for this many probes, then down for half as many
+const FLAP_PERIOD: u64 = 20;
+
+// A small xorshift64* generator, so a seed always produces the same run
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        // xorshift gets stuck at zero
+        Rng(if seed == 0 { DEFAULT_SEED } else { seed })
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    // A float in [0, 1)
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+pub enum Profile {
+    Clean,
+    Lossy(f64),
+    Bursty,
+    Jittery,
+    Flapping,
+    Recorded(Vec<Option<Duration>>),
+}
+
+impl Profile {
+    // Anything that isn't a known profile name is treated as the path to a
+    // recorded run: one rtt in milliseconds per line, negative for a lost probe
+    pub fn from_arg(arg: &str) -> io::Result<Profile> {
+        Ok(match arg {
+            "clean" => Profile::Clean,
+            "bursty" => Profile::Bursty,
+            "jittery" => Profile::Jittery,
+            "flapping" => Profile::Flapping,
+            _ if arg.starts_with("lossy:") => {
+                let percent = arg["lossy:".len()..].trim_end_matches('%')
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|p| *p >= 0.0 && *p <= 100.0)
+                    .ok_or_else(||
+                        io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            "The loss for the 'lossy' profile must be a percentage between 0 and 100.",
+                        )
+                    )?;
+                Profile::Lossy(percent / 100.0)
+            },
+            _ => Profile::Recorded(read_recorded_run(arg)?),
+        })
+    }
+}
+
+fn read_recorded_run(path: &str) -> io::Result<Vec<Option<Duration>>> {
+    let contents = fs::read_to_string(path)?;
+    let run = contents.lines()
+        .map(str::trim)
+        .enumerate()
+        .filter(|(_, l)| !l.is_empty())
+        .map(|(i, l)| {
+            // Rtts too large for a `Duration`, or not numbers at all, like
+            // `inf` and `NaN`, are as invalid as text
+            let rtt = match l.parse::<f64>() {
+                Ok(ms) if ms < 0.0 => Some(None),
+                Ok(ms) => Duration::try_from_secs_f64(ms / 1000.0).ok().map(Some),
+                Err(_) => None,
+            };
+            rtt.ok_or_else(||
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("The recorded run '{}' contains an invalid rtt on line {}: '{}'.", path, i + 1, l),
+                )
+            )
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+
+    if run.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("The recorded run '{}' is empty.", path),
+        ));
+    }
+
+    Ok(run)
+}
+
+pub struct SimulatedTransport {
+    profile: Profile,
+    rng: Rng,
+    probes_sent: u64,
+    in_burst: bool,
+    // The rtt of the last probe that was sent, or `None` if it will be lost
+    pending: Option<Duration>,
+    seq: u16,
+    sent: Instant,
+    // The replies slower than the timeout, with when they come in
+    overdue: Vec<(u16, Instant)>,
+    late: Vec<LateReply>,
+}
+
+impl SimulatedTransport {
+    pub fn new(profile: Profile, seed: u64) -> SimulatedTransport {
+        SimulatedTransport {
+            profile,
+            rng: Rng::new(seed),
+            probes_sent: 0,
+            in_burst: false,
+            pending: None,
+            seq: 0,
+            sent: Instant::now(),
+            overdue: Vec::new(),
+            late: Vec::new(),
+        }
+    }
+
+    fn jitter(&mut self, spread_ms: f64) -> Duration {
+        let ms = BASE_RTT_MS + (self.rng.next_f64() - 0.5) * spread_ms;
+        Duration::from_secs_f64(ms / 1000.0)
+    }
+
+    fn next_outcome(&mut self) -> Option<Duration> {
+        match &self.profile {
+            Profile::Clean => Some(self.jitter(2.0)),
+            Profile::Lossy(loss) => {
+                let loss = *loss;
+                if self.rng.next_f64() < loss {
+                    None
+                } else {
+                    Some(self.jitter(4.0))
+                }
+            },
+            Profile::Bursty => {
+                let chance = if self.in_burst { BURST_END_CHANCE } else { BURST_START_CHANCE };
+                if self.rng.next_f64() < chance {
+                    self.in_burst = !self.in_burst;
+                }
+
+                if self.in_burst {
+                    None
+                } else {
+                    Some(self.jitter(4.0))
+                }
+            },
+            Profile::Jittery => {
+                // Exponentially distributed delay on top of the base rtt
+                let extra_ms = -(1.0 - self.rng.next_f64()).ln() * 40.0;
+                Some(Duration::from_secs_f64((BASE_RTT_MS + extra_ms) / 1000.0))
+            },
+            Profile::Flapping => {
+                if self.probes_sent % (FLAP_PERIOD + FLAP_PERIOD / 2) < FLAP_PERIOD {
+                    Some(self.jitter(4.0))
+                } else {
+                    None
+                }
+            },
+            Profile::Recorded(run) => run[(self.probes_sent % run.len() as u64) as usize],
+        }
+    }
+}
+
+impl Transport for SimulatedTransport {
+    fn send_ping(&mut self, data: &mut [u8], seq: u16) -> io::Result<usize> {
+        self.pending = self.next_outcome();
+        self.probes_sent += 1;
+        self.seq = seq;
+        self.sent = Instant::now();
+        Ok(data.len())
+    }
+
+    fn next_reply(&mut self, timeout: Duration) -> io::Result<Reply> {
+        let reply = match self.pending.take() {
+            Some(rtt) if rtt <= timeout => {
+                sleep(rtt);
+                Reply::Echo
+            },
+            pending => {
+                if let Some(due) = pending.and_then(|rtt| self.sent.checked_add(rtt)) {
+                    self.overdue.push((self.seq, due));
+                }
+                sleep(timeout);
+                Reply::Timeout
+            },
+        };
+
+        let now = Instant::now();
+        let (arrived, overdue) = self.overdue.iter().partition::<Vec<_>, _>(|(_, due)| *due <= now);
+        self.overdue = overdue;
+        self.late.extend(arrived.into_iter().map(|(seq, received_at)| LateReply { seq, received_at }));
+        Ok(reply)
+    }
+
+    fn take_late(&mut self) -> Vec<LateReply> {
+        mem::take(&mut self.late)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    const SAMPLES: usize = 20_000;
+
+    fn outcomes(profile: Profile, seed: u64) -> Vec<Option<Duration>> {
+        let mut transport = SimulatedTransport::new(profile, seed);
+        (0..SAMPLES)
+            .map(|_| {
+                let outcome = transport.next_outcome();
+                transport.probes_sent += 1;
+                outcome
+            })
+            .collect()
+    }
+
+    fn loss_rate(outcomes: &[Option<Duration>]) -> f64 {
+        outcomes.iter().filter(|o| o.is_none()).count() as f64 / outcomes.len() as f64
+    }
+
+    fn mean_ms(outcomes: &[Option<Duration>]) -> f64 {
+        let rtts = outcomes.iter().flatten().map(|d| d.as_secs_f64() * 1000.0).collect::<Vec<_>>();
+        rtts.iter().sum::<f64>() / rtts.len() as f64
+    }
+
+    // The lengths of each run of lost probes
+    fn bursts(outcomes: &[Option<Duration>]) -> Vec<usize> {
+        outcomes.split(Option::is_some).map(<[_]>::len).filter(|l| *l > 0).collect()
+    }
+
+    #[test]
+    fn a_seed_always_gives_the_same_run() {
+        assert_eq!(outcomes(Profile::Bursty, 7), outcomes(Profile::Bursty, 7));
+        assert_ne!(outcomes(Profile::Bursty, 7), outcomes(Profile::Bursty, 8));
+        // Zero would get xorshift stuck
+        assert_eq!(outcomes(Profile::Jittery, 0), outcomes(Profile::Jittery, DEFAULT_SEED));
+    }
+
+    #[test]
+    fn clean_never_loses() {
+        let outcomes = outcomes(Profile::Clean, DEFAULT_SEED);
+        assert_eq!(loss_rate(&outcomes), 0.0);
+        for rtt in outcomes.iter().flatten() {
+            let ms = rtt.as_secs_f64() * 1000.0;
+            assert!((BASE_RTT_MS - 1.0..=BASE_RTT_MS + 1.0).contains(&ms), "{}ms", ms);
+        }
+    }
+
+    #[test]
+    fn lossy_loses_about_as_asked() {
+        for percent in &[0.0, 5.0, 50.0, 100.0] {
+            let loss = loss_rate(&outcomes(Profile::Lossy(percent / 100.0), DEFAULT_SEED));
+            assert!((loss - percent / 100.0).abs() < 0.01, "{}% asked, {} lost", percent, loss);
+        }
+    }
+
+    #[test]
+    fn bursty_loses_in_bursts() {
+        let outcomes = outcomes(Profile::Bursty, DEFAULT_SEED);
+        let bursts = bursts(&outcomes);
+
+        // Bursts end with a chance of 0.3 per probe, so they last about 3.3
+        // probes, and start often enough to lose about a seventh of them
+        let mean_burst = bursts.iter().sum::<usize>() as f64 / bursts.len() as f64;
+        assert!((mean_burst - 1.0 / BURST_END_CHANCE).abs() < 0.5, "bursts of {}", mean_burst);
+        let expected_loss = BURST_START_CHANCE / (BURST_START_CHANCE + BURST_END_CHANCE);
+        assert!((loss_rate(&outcomes) - expected_loss).abs() < 0.03);
+        assert!(bursts.iter().any(|b| *b >= 5));
+    }
+
+    #[test]
+    fn jittery_only_adds_delay() {
+        let outcomes = outcomes(Profile::Jittery, DEFAULT_SEED);
+        assert_eq!(loss_rate(&outcomes), 0.0);
+        assert!(outcomes.iter().flatten().all(|rtt| rtt.as_secs_f64() * 1000.0 >= BASE_RTT_MS));
+        // The extra delay averages 40ms
+        assert!((mean_ms(&outcomes) - BASE_RTT_MS - 40.0).abs() < 3.0);
+    }
+
+    #[test]
+    fn flapping_alternates_up_and_down() {
+        let outcomes = outcomes(Profile::Flapping, DEFAULT_SEED);
+        let period = (FLAP_PERIOD + FLAP_PERIOD / 2) as usize;
+        for cycle in outcomes.chunks(period).filter(|c| c.len() == period) {
+            assert!(cycle[..FLAP_PERIOD as usize].iter().all(Option::is_some));
+            assert!(cycle[FLAP_PERIOD as usize..].iter().all(Option::is_none));
+        }
+    }
+
+    #[test]
+    fn recorded_runs_repeat() {
+        let run = vec![Some(Duration::from_millis(5)), None];
+        let outcomes = outcomes(Profile::Recorded(run.clone()), DEFAULT_SEED);
+        assert_eq!(&outcomes[..4], &[run[0], run[1], run[0], run[1]]);
+    }
+
+    #[test]
+    fn parses_profiles() {
+        assert!(matches!(Profile::from_arg("clean"), Ok(Profile::Clean)));
+        assert!(matches!(Profile::from_arg("lossy:5%"), Ok(Profile::Lossy(l)) if (l - 0.05).abs() < 1e-9));
+        assert!(matches!(Profile::from_arg("lossy:5"), Ok(Profile::Lossy(_))));
+        assert!(Profile::from_arg("lossy:150%").is_err());
+        assert!(Profile::from_arg("lossy:").is_err());
+
+        let path = std::env::temp_dir().join(format!("ping-recorded-{}", process::id()));
+        fs::write(&path, "5\n\n -1\n12.5\n").unwrap();
+        let profile = Profile::from_arg(path.to_str().unwrap());
+        fs::write(&path, "5\nlost\n").unwrap();
+        let invalid = Profile::from_arg(path.to_str().unwrap());
+        fs::remove_file(&path).unwrap();
+
+        match profile {
+            Ok(Profile::Recorded(run)) => assert_eq!(run, vec![
+                Some(Duration::from_millis(5)),
+                None,
+                Some(Duration::from_micros(12_500)),
+            ]),
+            _ => panic!("expected a recorded run"),
+        }
+        assert!(invalid.is_err());
+        assert!(Profile::from_arg("/nonexistent/recording").is_err());
+    }
+
+    #[test]
+    fn rejects_rtts_too_large_for_a_duration() {
+        let path = std::env::temp_dir().join(format!("ping-recorded-huge-{}", process::id()));
+        let path_str = path.to_str().unwrap();
+        for rtt in &["inf", "NaN", "1e30", "-NaN"] {
+            fs::write(&path, format!("5\n\n{}\n", rtt)).unwrap();
+            match Profile::from_arg(path_str) {
+                Err(e) => {
+                    assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+                    assert_eq!(
+                        e.to_string(),
+                        format!("The recorded run '{}' contains an invalid rtt on line 3: '{}'.", path_str, rtt),
+                    );
+                },
+                Ok(_) => panic!("'{}' was accepted", rtt),
+            }
+        }
+
+        // Negative infinity is as lost as any other negative rtt
+        fs::write(&path, "-inf\n").unwrap();
+        let lost = Profile::from_arg(path_str);
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(lost, Ok(Profile::Recorded(run)) if run == vec![None]));
+    }
+}
@@ -0,0 +1,361 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::extremes::{Extremes, Moment};
+use crate::loss_map::LossMap;
+use crate::pinger::{PingStats, RttSpread};
+
+// Bumped whenever the format changes; files with another version are ignored.
+// It's separate from the JSON output's `SCHEMA_VERSION`: the state file is
+// only read back by ping itself, so it can change without breaking anyone's
+// parser, and new optional keys don't need a bump at all.
+const STATE_VERSION: u32 = 1;
+
+pub const STATE_SAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+// Everything needed to carry on a run after a restart, besides the loss map
+pub struct RunState {
+    pub stats: PingStats,
+    pub saved_at: SystemTime,
+    // The icmp_seq the next probe goes out with, if the file has it
+    pub next_seq: Option<u16>,
+    // How long the saved runs took altogether, not counting the gaps between them
+    pub elapsed: Duration,
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+fn parse_field<T: std::str::FromStr>(value: Option<&str>, name: &str) -> io::Result<T> {
+    value.ok_or_else(|| invalid(format!("it has no '{}'", name)))?
+        .parse()
+        .map_err(|_| invalid(format!("its '{}' is invalid", name)))
+}
+
+// `count:min:max:total:total_squares`
+fn parse_rtt_spread(saved: &str) -> Option<RttSpread> {
+    let (count, rest) = saved.split_once(':')?;
+    let count = count.parse().ok()?;
+    let rest = rest.split(':').map(|p| p.parse().ok()).collect::<Option<Vec<f64>>>()?;
+    match *rest.as_slice() {
+        [min, max, total, total_squares] => Some(RttSpread { count, min, max, total, total_squares }),
+        _ => None,
+    }
+}
+
+// `seq:micros`, with the microseconds since the unix epoch
+fn parse_moment(saved: &str) -> Option<Moment> {
+    let (seq, micros) = saved.split_once(':')?;
+    Some(Moment { seq: seq.parse().ok()?, at: UNIX_EPOCH.checked_add(Duration::from_micros(micros.parse().ok()?))? })
+}
+
+fn format_moment(moment: Moment) -> String {
+    format!("{}:{}", moment.seq, moment.at.duration_since(UNIX_EPOCH).unwrap_or_default().as_micros())
+}
+
+// Like `parse_field`, for fields files saved before they were kept don't have
+fn parse_optional<T>(value: Option<&str>, name: &str, parse: impl Fn(&str) -> Option<T>) -> io::Result<Option<T>> {
+    value.map(|v| parse(v).ok_or_else(|| invalid(format!("its '{}' is invalid", name)))).transpose()
+}
+
+// The state is stored as `key=value` lines, starting with the version
+pub struct StateFile {
+    path: PathBuf,
+}
+
+impl StateFile {
+    pub fn new(path: impl Into<PathBuf>) -> StateFile {
+        StateFile { path: path.into() }
+    }
+
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+
+    // Returns `Ok(None)` if there's no state to restore yet. `loss_map` is
+    // filled with the saved outcomes, if there are any.
+    pub fn load(&self, loss_map: Option<&mut LossMap>) -> io::Result<Option<RunState>> {
+        let contents = match fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        let field = |name: &str| contents.lines()
+            .filter_map(|l| l.split_once('='))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value);
+
+        let version: u32 = parse_field(field("version"), "version")?;
+        if version != STATE_VERSION {
+            return Err(invalid(format!("it's version {}, but only version {} is supported", version, STATE_VERSION)));
+        }
+
+        let mut stats = PingStats {
+            num_sent: parse_field(field("num_sent"), "num_sent")?,
+            num_received: parse_field(field("num_received"), "num_received")?,
+            total_rtt: parse_field(field("total_rtt"), "total_rtt")?,
+            rtts: match field("rtt_spread") {
+                Some(saved) => parse_rtt_spread(saved).ok_or_else(|| invalid("its 'rtt_spread' is invalid"))?,
+                // Files saved before the spread was kept
+                None => RttSpread::default(),
+            },
+            extremes: Extremes::default(),
+        };
+        stats.extremes.fastest = parse_optional(field("fastest"), "fastest", parse_moment)?;
+        stats.extremes.slowest = parse_optional(field("slowest"), "slowest", parse_moment)?;
+        stats.extremes.longest_outage = parse_optional(field("longest_outage"), "longest_outage", |saved| {
+            let (moment, ms) = saved.rsplit_once(':')?;
+            Some((parse_moment(moment)?, Duration::from_millis(ms.parse().ok()?)))
+        })?;
+        if stats.num_received > stats.num_sent {
+            return Err(invalid("it has more replies than probes"));
+        }
+
+        // A time too far off for the clock can only be from a corrupt file
+        let saved_at = UNIX_EPOCH.checked_add(Duration::from_secs(parse_field(field("saved_at"), "saved_at")?))
+            .ok_or_else(|| invalid("its 'saved_at' is invalid"))?;
+
+        // Both are missing from files saved before they were kept
+        let next_seq = match field("next_seq") {
+            Some(saved) => Some(parse_field(Some(saved), "next_seq")?),
+            None => None,
+        };
+        let elapsed = match field("elapsed_ms") {
+            Some(saved) => Duration::from_millis(parse_field(Some(saved), "elapsed_ms")?),
+            None => Duration::ZERO,
+        };
+
+        if let (Some(loss_map), Some(saved)) = (loss_map, field("loss_map")) {
+            let (dropped, symbols) = saved.split_once(':')
+                .ok_or_else(|| invalid("its 'loss_map' is invalid"))?;
+            let dropped = parse_field(Some(dropped), "loss_map")?;
+            if !loss_map.restore(dropped, symbols) {
+                return Err(invalid("its 'loss_map' is invalid"));
+            }
+        }
+
+        Ok(Some(RunState { stats, saved_at, next_seq, elapsed }))
+    }
+
+    // Writes to a temporary file first, so a crash never leaves half a file
+    pub fn save(&self, state: &RunState, loss_map: Option<&LossMap>) -> io::Result<()> {
+        let saved_at = state.saved_at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let stats = state.stats;
+
+        let mut contents = format!(
+            "version={}\nsaved_at={}\nnum_sent={}\nnum_received={}\ntotal_rtt={}\n",
+            STATE_VERSION,
+            saved_at,
+            stats.num_sent,
+            stats.num_received,
+            stats.total_rtt,
+        );
+        if let Some(next_seq) = state.next_seq {
+            contents.push_str(&format!("next_seq={}\n", next_seq));
+        }
+        contents.push_str(&format!("elapsed_ms={}\n", state.elapsed.as_millis()));
+        let rtts = stats.rtts;
+        contents.push_str(&format!(
+            "rtt_spread={}:{}:{}:{}:{}\n",
+            rtts.count,
+            rtts.min,
+            rtts.max,
+            rtts.total,
+            rtts.total_squares,
+        ));
+        let extremes = stats.extremes;
+        if let Some(fastest) = extremes.fastest {
+            contents.push_str(&format!("fastest={}\n", format_moment(fastest)));
+        }
+        if let Some(slowest) = extremes.slowest {
+            contents.push_str(&format!("slowest={}\n", format_moment(slowest)));
+        }
+        if let Some((started, lasted)) = extremes.longest_outage {
+            contents.push_str(&format!("longest_outage={}:{}\n", format_moment(started), lasted.as_millis()));
+        }
+        if let Some(loss_map) = loss_map {
+            contents.push_str(&format!("loss_map={}:{}\n", loss_map.dropped(), loss_map.symbols()));
+        }
+
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        fs::write(&tmp, contents)?;
+        fs::rename(&tmp, &self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+    use crate::loss_map::Outcome;
+
+    // A state file of its own for each test, removed when it's done
+    struct TempStateFile(StateFile);
+
+    impl TempStateFile {
+        fn new(name: &str) -> TempStateFile {
+            TempStateFile(StateFile::new(std::env::temp_dir().join(format!("ping-state-{}-{}", name, process::id()))))
+        }
+
+        fn write(&self, contents: &str) {
+            fs::write(self.0.path(), contents).unwrap();
+        }
+    }
+
+    impl Drop for TempStateFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(self.0.path());
+        }
+    }
+
+    fn at(secs: u64, seq: u16) -> Moment {
+        Moment { seq, at: UNIX_EPOCH + Duration::from_secs(secs) }
+    }
+
+    // Ten probes, with the fourth and fifth lost
+    fn synthetic_run() -> RunState {
+        let mut stats = PingStats::default();
+        for (seq, ms) in [12.5, 8.25, 30.0, -1.0, -1.0, 14.0, 9.0, 11.0, 13.0, 10.5].iter().enumerate() {
+            let probe = at(1_600_000_000 + seq as u64, seq as u16);
+            stats.num_sent += 1;
+            if *ms < 0.0 {
+                stats.extremes.lost(probe);
+                continue;
+            }
+            let rtt = Duration::from_secs_f64(ms / 1000.0);
+            stats.num_received += 1;
+            stats.total_rtt += rtt.as_millis();
+            stats.extremes.reply(stats.rtts, *ms, probe, probe.at);
+            stats.rtts.record(rtt);
+        }
+
+        RunState {
+            stats,
+            saved_at: UNIX_EPOCH + Duration::from_secs(1_600_000_010),
+            next_seq: Some(10),
+            elapsed: Duration::from_millis(4_512),
+        }
+    }
+
+    #[test]
+    fn loads_what_it_saved() {
+        let file = TempStateFile::new("round-trip");
+        let saved = synthetic_run();
+        file.0.save(&saved, None).unwrap();
+
+        let loaded = file.0.load(None).unwrap().unwrap();
+        assert_eq!(loaded.stats, saved.stats);
+        assert_eq!(loaded.stats.extremes.fastest, Some(at(1_600_000_001, 1)));
+        assert_eq!(loaded.stats.extremes.longest_outage, Some((at(1_600_000_003, 3), Duration::from_secs(2))));
+        assert_eq!(loaded.saved_at, saved.saved_at);
+    }
+
+    #[test]
+    fn carries_on_the_numbering_and_the_time() {
+        let file = TempStateFile::new("continuity");
+        file.0.save(&synthetic_run(), None).unwrap();
+
+        let loaded = file.0.load(None).unwrap().unwrap();
+        assert_eq!(loaded.next_seq, Some(10));
+        assert_eq!(loaded.elapsed, Duration::from_millis(4_512));
+
+        // Numbering past the last sequence number wraps around, like the probes do
+        file.0.save(&RunState { next_seq: Some(u16::MAX), ..synthetic_run() }, None).unwrap();
+        assert_eq!(file.0.load(None).unwrap().unwrap().next_seq, Some(u16::MAX));
+
+        // Files saved before they were kept have neither
+        file.write("version=1\nsaved_at=0\nnum_sent=2\nnum_received=1\ntotal_rtt=12\n");
+        let loaded = file.0.load(None).unwrap().unwrap();
+        assert_eq!((loaded.next_seq, loaded.elapsed), (None, Duration::ZERO));
+        assert_eq!(loaded.stats.rtts, RttSpread::default());
+        assert_eq!(loaded.stats.extremes, Extremes::default());
+    }
+
+    #[test]
+    fn keeps_the_loss_map() {
+        let file = TempStateFile::new("loss-map");
+        let mut loss_map = LossMap::new(4);
+        for outcome in &[Outcome::Reply, Outcome::Lost, Outcome::Reply, Outcome::Error, Outcome::Reply] {
+            loss_map.push(*outcome);
+        }
+        file.0.save(&synthetic_run(), Some(&loss_map)).unwrap();
+
+        let mut restored = LossMap::new(4);
+        file.0.load(Some(&mut restored)).unwrap().unwrap();
+        assert_eq!((restored.dropped(), restored.symbols()), (loss_map.dropped(), loss_map.symbols()));
+    }
+
+    #[test]
+    fn has_nothing_to_restore_without_a_file() {
+        assert!(TempStateFile::new("missing").0.load(None).unwrap().is_none());
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let file = TempStateFile::new("version");
+        file.0.save(&synthetic_run(), None).unwrap();
+        let saved = fs::read_to_string(file.0.path()).unwrap();
+        file.write(&saved.replace("version=1\n", "version=2\n"));
+
+        let e = file.0.load(None).err().unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(e.to_string(), "it's version 2, but only version 1 is supported");
+    }
+
+    #[test]
+    fn rejects_truncated_files_without_panicking() {
+        let file = TempStateFile::new("truncated");
+        let mut loss_map = LossMap::new(10);
+        loss_map.push(Outcome::Lost);
+        file.0.save(&synthetic_run(), Some(&loss_map)).unwrap();
+        let saved = fs::read_to_string(file.0.path()).unwrap();
+
+        // Cut in the middle of each line and after it, where anything before
+        // the last of the fields that always have to be there is missing one
+        let required = saved.find("total_rtt=").unwrap();
+        let mut ends = vec![0];
+        let mut start = 0;
+        for line in saved.split_inclusive('\n') {
+            ends.extend(&[start + line.len() / 2, start + line.len()]);
+            start += line.len();
+        }
+        for end in ends {
+            file.write(&saved[..end]);
+            let loaded = file.0.load(Some(&mut LossMap::new(10)));
+            if end < required {
+                assert_eq!(loaded.err().map(|e| e.kind()), Some(io::ErrorKind::InvalidData), "cut at {}", end);
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_corrupt_files_without_panicking() {
+        let file = TempStateFile::new("corrupt");
+        let corrupt = [
+            ("", "it has no 'version'"),
+            ("\0\0\0\0", "it has no 'version'"),
+            ("version=one", "its 'version' is invalid"),
+            ("version=1\nsaved_at=0\nnum_sent=-2\nnum_received=1\ntotal_rtt=12", "its 'num_sent' is invalid"),
+            ("version=1\nsaved_at=0\nnum_sent=1\nnum_received=2\ntotal_rtt=12", "it has more replies than probes"),
+            ("version=1\nsaved_at=18446744073709551615\nnum_sent=2\nnum_received=1\ntotal_rtt=12", "its 'saved_at' is invalid"),
+            ("version=1\nsaved_at=0\nnum_sent=2\nnum_received=1\ntotal_rtt=12\nnext_seq=65536", "its 'next_seq' is invalid"),
+            ("version=1\nsaved_at=0\nnum_sent=2\nnum_received=1\ntotal_rtt=12\nrtt_spread=1:2:3", "its 'rtt_spread' is invalid"),
+            ("version=1\nsaved_at=0\nnum_sent=2\nnum_received=1\ntotal_rtt=12\nfastest=3", "its 'fastest' is invalid"),
+            ("version=1\nsaved_at=0\nnum_sent=2\nnum_received=1\ntotal_rtt=12\nslowest=1:notatime", "its 'slowest' is invalid"),
+            ("version=1\nsaved_at=0\nnum_sent=2\nnum_received=1\ntotal_rtt=12\nlongest_outage=1:2", "its 'longest_outage' is invalid"),
+            ("version=1\nsaved_at=0\nnum_sent=2\nnum_received=1\ntotal_rtt=12\nloss_map=0:.x?", "its 'loss_map' is invalid"),
+            ("version=1\nsaved_at=0\nnum_sent=2\nnum_received=1\ntotal_rtt=12\nloss_map=..", "its 'loss_map' is invalid"),
+        ];
+        for (contents, message) in &corrupt {
+            file.write(contents);
+            let e = file.0.load(Some(&mut LossMap::new(10))).err().unwrap_or_else(|| panic!("{:?} was loaded", contents));
+            assert_eq!(e.kind(), io::ErrorKind::InvalidData, "{:?}", contents);
+            assert_eq!(e.to_string(), *message, "{:?}", contents);
+        }
+    }
+}
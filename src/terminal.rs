@@ -0,0 +1,31 @@
+#[cfg(unix)]
+pub fn stdout_is_tty() -> bool {
+    unsafe { libc::isatty(libc::STDOUT_FILENO) == 1 }
+}
+
+#[cfg(not(unix))]
+pub fn stdout_is_tty() -> bool {
+    false
+}
+
+// The width of the terminal stdout is connected to, or `None` if it isn't one
+#[cfg(unix)]
+pub fn stdout_width() -> Option<usize> {
+    if !stdout_is_tty() {
+        return None;
+    }
+
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    let result = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+
+    if result == 0 && size.ws_col > 0 {
+        Some(size.ws_col as usize)
+    } else {
+        None
+    }
+}
+
+#[cfg(not(unix))]
+pub fn stdout_width() -> Option<usize> {
+    None
+}
@@ -0,0 +1,105 @@
+use std::fmt;
+use std::time::Duration;
+
+use crate::output::decimal;
+
+// Where time went inside the tool for a single probe
+#[derive(Clone, Copy, Debug)]
+pub struct ProbeTiming {
+    // How much later than planned the probe was sent
+    pub sched_late: Duration,
+    // How long the send call took
+    pub send: Duration,
+}
+
+fn ms(d: Duration) -> String {
+    decimal(d.as_secs_f64() * 1000.0, 1)
+}
+
+impl fmt::Display for ProbeTiming {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[sched+{} send {} ms]", ms(self.sched_late), ms(self.send))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Spread {
+    count: u64,
+    total: Duration,
+    max: Duration,
+}
+
+impl Spread {
+    fn record(&mut self, d: Duration) {
+        self.count += 1;
+        self.total += d;
+        self.max = self.max.max(d);
+    }
+}
+
+impl fmt::Display for Spread {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mean = if self.count == 0 { Duration::from_secs(0) } else { self.total.div_f64(self.count as f64) };
+        write!(f, "{}/{}", ms(mean), ms(self.max))
+    }
+}
+
+// The mean and worst of each part of the timing breakdown over a run, so
+// overhead in the tool itself can be told apart from the network's
+#[derive(Default)]
+pub struct TimingReport {
+    sched_late: Spread,
+    send: Spread,
+    print: Spread,
+}
+
+impl TimingReport {
+    pub fn record(&mut self, timing: ProbeTiming, print: Duration) {
+        self.sched_late.record(timing.sched_late);
+        self.send.record(timing.send);
+        self.print.record(print);
+    }
+}
+
+impl fmt::Display for TimingReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Time spent in ping (mean/max ms): sched+{}, send {}, print {}",
+            self.sched_late,
+            self.send,
+            self.print,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: f64) -> Duration {
+        Duration::from_secs_f64(ms / 1000.0)
+    }
+
+    #[test]
+    fn shows_each_probes_timing() {
+        let timing = ProbeTiming { sched_late: ms(0.14), send: ms(0.26) };
+        assert_eq!(timing.to_string(), "[sched+0.1 send 0.3 ms]");
+    }
+
+    #[test]
+    fn sums_up_the_mean_and_worst() {
+        let mut report = TimingReport::default();
+        report.record(ProbeTiming { sched_late: ms(0.0), send: ms(0.2) }, ms(0.1));
+        report.record(ProbeTiming { sched_late: ms(3.0), send: ms(0.4) }, ms(0.1));
+        report.record(ProbeTiming { sched_late: ms(0.0), send: ms(0.3) }, ms(1.0));
+
+        assert_eq!(report.to_string(), "Time spent in ping (mean/max ms): sched+1.0/3.0, send 0.3/0.4, print 0.4/1.0");
+    }
+
+    #[test]
+    fn sums_up_nothing() {
+        assert_eq!(
+            TimingReport::default().to_string(),
+            "Time spent in ping (mean/max ms): sched+0.0/0.0, send 0.0/0.0, print 0.0/0.0",
+        );
+    }
+}
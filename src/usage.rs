@@ -0,0 +1,87 @@
+use std::fmt;
+use std::time::Duration;
+
+use crate::output::decimal;
+
+// How much of the machine this process has used so far
+pub struct ResourceUsage {
+    pub user_time: Duration,
+    pub system_time: Duration,
+    pub peak_rss_bytes: u64,
+}
+
+impl fmt::Display for ResourceUsage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Self: {}s user, {}s system, {} MiB peak memory",
+            decimal(self.user_time.as_secs_f64(), 3),
+            decimal(self.system_time.as_secs_f64(), 3),
+            decimal(self.peak_rss_bytes as f64 / (1024.0 * 1024.0), 1),
+        )
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn duration(time: libc::timeval) -> Duration {
+    Duration::from_secs(time.tv_sec as u64) + Duration::from_micros(time.tv_usec as u64)
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub fn resource_usage() -> Option<ResourceUsage> {
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    if unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) } != 0 {
+        return None;
+    }
+
+    // Linux reports the peak in kilobytes, and macOS in bytes
+    let scale = if cfg!(target_os = "linux") { 1024 } else { 1 };
+
+    Some(ResourceUsage {
+        user_time: duration(usage.ru_utime),
+        system_time: duration(usage.ru_stime),
+        peak_rss_bytes: usage.ru_maxrss as u64 * scale,
+    })
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn resource_usage() -> Option<ResourceUsage> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_compactly() {
+        let usage = ResourceUsage {
+            user_time: Duration::from_millis(1250),
+            system_time: Duration::from_micros(300),
+            peak_rss_bytes: 3 * 1024 * 1024 + 512 * 1024,
+        };
+        assert_eq!(usage.to_string(), "Self: 1.250s user, 0.000s system, 3.5 MiB peak memory");
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    #[test]
+    fn is_plausible() {
+        let before = resource_usage().unwrap();
+
+        // Something to account for
+        let mut n = 0u64;
+        for i in 0..5_000_000u64 {
+            n = n.wrapping_mul(31).wrapping_add(i);
+        }
+        assert_ne!(std::hint::black_box(n), 1);
+
+        let after = resource_usage().unwrap();
+        assert!(after.user_time >= before.user_time);
+        assert!(after.system_time >= before.system_time);
+        assert!(after.peak_rss_bytes >= before.peak_rss_bytes);
+        // A test binary needs more than a megabyte, and far less than 16 GiB
+        assert!(after.peak_rss_bytes > 1024 * 1024, "{} bytes", after.peak_rss_bytes);
+        assert!(after.peak_rss_bytes < 16 * 1024 * 1024 * 1024, "{} bytes", after.peak_rss_bytes);
+        assert!(after.user_time + after.system_time < Duration::from_secs(3600));
+    }
+}
@@ -0,0 +1,181 @@
+use clap::ArgMatches;
+
+enum Rule {
+    // The flag can't be given along with any of the others
+    Conflicts(&'static str, &'static [&'static str]),
+    // The flag can only be given along with at least one of the others
+    Requires(&'static str, &'static [&'static str]),
+}
+
+// Every documented combination of flags that doesn't make sense. New flags
+// only need a line here.
+const RULES: &[Rule] = &[
+    Rule::Conflicts("ipv4", &["ipv6"]),
+    Rule::Requires("require", &["packet_count", "max_seq"]),
+    Rule::Requires("loss_map_size", &["loss_map"]),
+    Rule::Requires("hook_shell", &["on_down", "on_up"]),
+    Rule::Requires("hook_timeout", &["on_down", "on_up"]),
+    Rule::Conflicts("id", &["id_hex"]),
+    Rule::Requires("ttl_exceeded_ok", &["ttl"]),
+    Rule::Conflicts("first_result_fd", &["first_result_file"]),
+    Rule::Conflicts("no_coalesce", &["coalesce_after", "coalesce_interval"]),
+    Rule::Requires("oneline", &["packet_count", "max_seq"]),
+    Rule::Conflicts("oneline", &["loss_map", "show_send_time", "interval_ramp", "width", "verbose", "self_stats", "timing_breakdown", "show_invalid"]),
+    Rule::Requires("oneline_width", &["oneline"]),
+    Rule::Requires("loss_value", &["rtt_only"]),
+    Rule::Requires("skip_loss", &["rtt_only"]),
+    Rule::Conflicts("skip_loss", &["loss_value"]),
+    Rule::Conflicts("rtt_only", &["oneline", "loss_map", "interval_ramp", "show_send_time", "width", "timing_breakdown", "show_invalid"]),
+    Rule::Requires("simulate_seed", &["simulate"]),
+    // Simulated replies don't go through the matching these change
+    Rule::Conflicts("simulate", &["accept_from", "strict_id"]),
+    // A restored run carries on with its own sequence numbers
+    Rule::Conflicts("state_file", &["seq_start", "max_seq"]),
+];
+
+// How the flag is written on the command line
+fn flag(name: &str) -> String {
+    match name {
+        "ipv4" => "-4".to_owned(),
+        "ipv6" => "-6".to_owned(),
+        "packet_count" => "--count".to_owned(),
+        "timeout" => "--wait".to_owned(),
+        _ => format!("--{}", name.replace('_', "-")),
+    }
+}
+
+fn flag_list(names: &[&str]) -> String {
+    names.iter().map(|n| format!("'{}'", flag(n))).collect::<Vec<_>>().join(" or ")
+}
+
+fn check(rule: &Rule, config: &ArgMatches) -> Option<String> {
+    match rule {
+        Rule::Conflicts(name, others) => {
+            let given = others.iter().cloned().filter(|o| config.is_present(o)).collect::<Vec<_>>();
+            if config.is_present(name) && !given.is_empty() {
+                Some(format!("'{}' can't be used with {}", flag(name), flag_list(&given)))
+            } else {
+                None
+            }
+        },
+        Rule::Requires(name, others) => {
+            if config.is_present(name) && !others.iter().any(|o| config.is_present(o)) {
+                Some(format!("'{}' can only be used with {}", flag(name), flag_list(others)))
+            } else {
+                None
+            }
+        },
+    }
+}
+
+// Checks every rule, so all the problems can be reported at once
+pub fn validate(config: &ArgMatches) -> Result<(), String> {
+    let violations = RULES.iter()
+        .filter_map(|rule| check(rule, config))
+        .collect::<Vec<_>>();
+
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("These flags can't be used together like this:\n  {}", violations.join("\n  ")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::ErrorKind;
+    use crate::{app, HelpTexts};
+
+    // The arguments giving `name`, with a value if it takes one, or `None` if
+    // this build doesn't have the flag
+    fn given(name: &str) -> Option<Vec<String>> {
+        let help = HelpTexts::new();
+        let parse = |args: &[String]| app(&help).get_matches_from_safe(
+            ["ping", "localhost"].iter().map(|a| a.to_string()).chain(args.iter().cloned())
+        );
+
+        let mut args = vec![flag(name)];
+        match parse(&args) {
+            Ok(_) => Some(args),
+            Err(ref e) if e.kind == ErrorKind::EmptyValue => {
+                args.push("1".to_owned());
+                Some(args)
+            },
+            // Only the simulate flags are left out of some builds
+            Err(ref e) if e.kind == ErrorKind::UnknownArgument && name.starts_with("simulate") => None,
+            Err(e) => panic!("'{}' could not be given: {}", flag(name), e),
+        }
+    }
+
+    fn violation(rule: &Rule, names: &[&str]) -> Option<Option<String>> {
+        let mut args = vec!["ping".to_owned(), "localhost".to_owned()];
+        for name in names {
+            args.extend(given(name)?);
+        }
+        let help = HelpTexts::new();
+        let config = app(&help).get_matches_from_safe(&args).unwrap_or_else(|e| panic!("{:?}: {}", args, e));
+        Some(check(rule, &config))
+    }
+
+    #[test]
+    fn every_rule_accepts_and_rejects() {
+        for rule in RULES {
+            match rule {
+                Rule::Conflicts(name, others) => {
+                    if let Some(alone) = violation(rule, &[name]) {
+                        assert_eq!(alone, None, "'{}' alone", name);
+                    }
+                    for other in *others {
+                        if let Some(both) = violation(rule, &[name, other]) {
+                            assert!(both.is_some(), "'{}' with '{}'", name, other);
+                        }
+                    }
+                },
+                Rule::Requires(name, others) => {
+                    if let Some(alone) = violation(rule, &[name]) {
+                        assert!(alone.is_some(), "'{}' alone", name);
+                    }
+                    for other in *others {
+                        if let Some(both) = violation(rule, &[name, other]) {
+                            assert_eq!(both, None, "'{}' with '{}'", name, other);
+                        }
+                    }
+                },
+            }
+        }
+    }
+
+    #[test]
+    fn reports_every_violation() {
+        let help = HelpTexts::new();
+        let config = app(&help)
+            .get_matches_from_safe(["ping", "localhost", "-4", "-6", "--loss-map-size", "5"])
+            .unwrap();
+        assert_eq!(validate(&config), Err(concat!(
+            "These flags can't be used together like this:\n",
+            "  '-4' can't be used with '-6'\n",
+            "  '--loss-map-size' can only be used with '--loss-map'",
+        ).to_owned()));
+
+        let config = app(&help).get_matches_from_safe(["ping", "localhost", "--count", "3", "--oneline"]).unwrap();
+        assert_eq!(validate(&config), Ok(()));
+    }
+
+    #[cfg(feature = "simulate")]
+    #[test]
+    fn rejects_matching_flags_with_simulated_replies() {
+        let help = HelpTexts::new();
+        let config = app(&help)
+            .get_matches_from_safe(["ping", "localhost", "--simulate", "clean", "--strict-id", "--accept-from", "192.0.2.1"])
+            .unwrap();
+        assert_eq!(
+            validate(&config),
+            Err("These flags can't be used together like this:
+  '--simulate' can't be used with '--accept-from' or '--strict-id'".to_owned()),
+        );
+
+        let config = app(&help).get_matches_from_safe(["ping", "localhost", "--simulate", "clean"]).unwrap();
+        assert_eq!(validate(&config), Ok(()));
+    }
+}
@@ -0,0 +1,113 @@
+use std::fmt;
+use std::io;
+
+// A health check rule: at least `needed` of `out_of` probes must get replies
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Requirement {
+    pub needed: u64,
+    pub out_of: u64,
+}
+
+impl Requirement {
+    // Parses `N/M`, where N can't be more than M
+    pub fn parse(s: &str) -> io::Result<Requirement> {
+        let invalid = ||
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The value for the 'require' flag must look like N/M, where N and M are positive integers and N is at most M.",
+            );
+
+        let mut parts = s.splitn(2, '/');
+        let needed = parts.next().and_then(|n| n.trim().parse().ok()).ok_or_else(invalid)?;
+        let out_of = parts.next().and_then(|m| m.trim().parse().ok()).ok_or_else(invalid)?;
+
+        if needed > out_of {
+            return Err(invalid());
+        }
+
+        Ok(Requirement { needed, out_of })
+    }
+
+    pub fn check(self, received: u64, sent: u64) -> Verdict {
+        Verdict { requirement: self, received, sent }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Verdict {
+    requirement: Requirement,
+    received: u64,
+    sent: u64,
+}
+
+impl Verdict {
+    pub fn passed(self) -> bool {
+        self.received >= self.requirement.needed
+    }
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({}/{} {} {}/{})",
+            if self.passed() { "PASS" } else { "FAIL" },
+            self.received,
+            self.sent,
+            if self.passed() { "≥" } else { "<" },
+            self.requirement.needed,
+            self.requirement.out_of,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn requirement(needed: u64, out_of: u64) -> Requirement {
+        Requirement { needed, out_of }
+    }
+
+    #[test]
+    fn parses_n_of_m() {
+        assert_eq!(Requirement::parse("8/10").unwrap(), requirement(8, 10));
+        assert_eq!(Requirement::parse(" 8 / 10 ").unwrap(), requirement(8, 10));
+        assert_eq!(Requirement::parse("10/10").unwrap(), requirement(10, 10));
+        assert_eq!(Requirement::parse("0/0").unwrap(), requirement(0, 0));
+    }
+
+    #[test]
+    fn rejects_anything_else() {
+        for s in &["", "8", "8/", "/10", "11/10", "-1/10", "8/-10", "8/10/12", "a/b", "8.5/10"] {
+            assert!(Requirement::parse(s).is_err(), "'{}' was accepted", s);
+        }
+    }
+
+    #[test]
+    fn passes_with_enough_replies() {
+        let verdict = requirement(8, 10).check(9, 10);
+        assert!(verdict.passed());
+        assert_eq!(verdict.to_string(), "PASS (9/10 ≥ 8/10)");
+
+        assert!(requirement(8, 10).check(8, 10).passed());
+    }
+
+    #[test]
+    fn fails_without_enough_replies() {
+        let verdict = requirement(8, 10).check(7, 10);
+        assert!(!verdict.passed());
+        assert_eq!(verdict.to_string(), "FAIL (7/10 < 8/10)");
+    }
+
+    #[test]
+    fn judges_runs_that_ended_early_by_the_replies() {
+        // Interrupted after 5 probes, all of which got replies
+        assert_eq!(requirement(8, 10).check(5, 5).to_string(), "FAIL (5/5 < 8/10)");
+        assert_eq!(requirement(3, 10).check(5, 5).to_string(), "PASS (5/5 ≥ 3/10)");
+    }
+
+    #[test]
+    fn judges_runs_with_nothing_sent() {
+        assert_eq!(requirement(1, 10).check(0, 0).to_string(), "FAIL (0/0 < 1/10)");
+        assert_eq!(requirement(0, 10).check(0, 0).to_string(), "PASS (0/0 ≥ 0/10)");
+    }
+}
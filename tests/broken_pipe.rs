@@ -0,0 +1,32 @@
+// What happens when whatever reads stdout goes away, like `ping host | head -2`
+#![cfg(feature = "simulate")]
+
+use std::io::{BufRead, BufReader, Read};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+#[test]
+fn exits_quietly_once_stdout_is_closed() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_ping"))
+        .args(["--simulate", "clean", "-c", "100", "192.0.2.7"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+    for _ in 0..2 {
+        assert!(lines.next().unwrap().unwrap().starts_with("Response received"));
+    }
+    let closed = Instant::now();
+    drop(lines);
+
+    let status = child.wait().unwrap();
+    let mut stderr = String::new();
+    child.stderr.take().unwrap().read_to_string(&mut stderr).unwrap();
+
+    assert_eq!(status.code(), Some(0));
+    // The next probe's line can't be written, so there's no waiting for the rest
+    assert!(closed.elapsed() < Duration::from_secs(5));
+    assert_eq!(stderr, "Simulating pings to 192.0.2.7...\n");
+}
@@ -0,0 +1,31 @@
+// Shared by the integration tests, each of which uses only some of it
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::{self, Command, Output};
+
+// Runs the ping binary with `args`, waiting for it to exit
+pub fn ping(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_ping"))
+        .args(args)
+        .output()
+        .expect("ping could not be run")
+}
+
+pub fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+pub fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+// Writes a recorded run for `--simulate` to a file of its own, one rtt in
+// milliseconds per probe and a negative one for each lost probe
+pub fn recording(name: &str, rtts: &[f64]) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("ping-recording-{}-{}", name, process::id()));
+    let lines = rtts.iter().map(f64::to_string).collect::<Vec<_>>();
+    fs::write(&path, lines.join("\n")).unwrap();
+    path
+}
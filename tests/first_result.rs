@@ -0,0 +1,72 @@
+// The first result written to a descriptor ping inherits, the way a
+// supervisor would pass it a pipe
+#![cfg(target_os = "linux")]
+
+mod common;
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::unix::io::FromRawFd;
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Output, Stdio};
+
+use common::stderr;
+
+// Runs ping with `args` and the write end of a pipe as descriptor `fd`,
+// returning how it went and everything written to the pipe
+fn with_pipe(fd: i32, args: &[&str]) -> (Output, String) {
+    let mut ends = [0; 2];
+    // Close-on-exec, so other tests' children don't keep the pipe open
+    assert_eq!(unsafe { libc::pipe2(ends.as_mut_ptr(), libc::O_CLOEXEC) }, 0);
+    let (read_end, write_end) = (ends[0], ends[1]);
+
+    let mut command = Command::new(env!("CARGO_BIN_EXE_ping"));
+    command.args(args).stdout(Stdio::piped()).stderr(Stdio::piped());
+    unsafe {
+        command.pre_exec(move || {
+            // Either way, `fd` is left open across the exec
+            let inherited = if write_end == fd {
+                libc::fcntl(fd, libc::F_SETFD, 0)
+            } else {
+                libc::dup2(write_end, fd)
+            };
+            if inherited < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let child = command.spawn().expect("ping could not be run");
+    unsafe { libc::close(write_end) };
+
+    let output = child.wait_with_output().unwrap();
+    let mut written = String::new();
+    unsafe { File::from_raw_fd(read_end) }.read_to_string(&mut written).unwrap();
+    (output, written)
+}
+
+fn assert_written_once(output: &Output, written: &str) {
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(output));
+    assert!(!stderr(output).contains("could not be written"), "{}", stderr(output));
+    assert_eq!(written.lines().count(), 1, "{}", written);
+    assert!(written.starts_with(r#"{"schema_version":"#), "{}", written);
+}
+
+#[cfg(feature = "simulate")]
+#[test]
+fn writes_the_first_result_to_an_inherited_pipe() {
+    let (output, written) = with_pipe(3, &["--simulate", "clean", "-c", "3", "--first-result-fd", "3", "192.0.2.7"]);
+    assert_written_once(&output, &written);
+    assert!(written.contains(r#""seq":0,"#), "{}", written);
+}
+
+// Ping's raw socket used to be able to end up with the descriptor's number
+#[test]
+fn keeps_the_pipe_apart_from_the_socket() {
+    if unsafe { libc::geteuid() } != 0 {
+        return;
+    }
+    let (output, written) = with_pipe(3, &["-c", "3", "--first-result-fd", "3", "127.0.0.1"]);
+    assert_written_once(&output, &written);
+    assert!(written.contains(r#""result":"reply""#), "{}", written);
+}
@@ -0,0 +1,62 @@
+// The hooks run for a simulated outage
+#![cfg(feature = "simulate")]
+
+mod common;
+
+use std::fs;
+use std::process;
+
+use common::{ping, stderr};
+
+#[test]
+fn hooks_are_told_about_the_outage() {
+    let dir = std::env::temp_dir().join(format!("ping-hooks-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    // One reply, three lost probes, and a reply again
+    let recording = dir.join("recording");
+    fs::write(&recording, "5\n-1\n-1\n-1\n5\n").unwrap();
+
+    let hook = |name: &str| format!("env | grep '^PING_' | sort > {}", dir.join(name).display());
+    let output = ping(&[
+        "--simulate", recording.to_str().unwrap(),
+        "-c", "5",
+        "-W", "100ms",
+        "--hook-shell",
+        "--on-down", &hook("down"),
+        "--on-up", &hook("up"),
+        "192.0.2.7",
+    ]);
+    let down = fs::read_to_string(dir.join("down"));
+    let up = fs::read_to_string(dir.join("up"));
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    let down = down.unwrap();
+    assert!(down.contains("PING_TARGET=192.0.2.7\n"));
+    assert!(down.contains("PING_SEQ=3\n"));
+    assert!(down.contains("PING_LOSS_PCT=75.00\n"));
+    let up = up.unwrap();
+    assert!(up.contains("PING_SEQ=4\n"));
+    assert!(up.contains("PING_LOSS_PCT=60.00\n"));
+    assert!(up.lines().any(|l| l.starts_with("PING_OUTAGE_SECS=")));
+}
+
+#[test]
+fn hooks_print_to_stderr() {
+    let recording = common::recording("hook-output", &[-1.0, 5.0]);
+    let output = ping(&[
+        "--simulate", recording.to_str().unwrap(),
+        "-c", "2",
+        "-W", "100ms",
+        "--down-after", "1",
+        "--rtt-only",
+        "--on-down", "echo hook-was-here",
+        "192.0.2.7",
+    ]);
+    fs::remove_file(&recording).unwrap();
+
+    let stdout = common::stdout(&output);
+    assert!(!stdout.contains("hook-was-here"), "{}", stdout);
+    assert!(stdout.lines().all(|l| l == "-1" || l.parse::<f64>().is_ok()), "{}", stdout);
+    assert!(stderr(&output).contains("hook-was-here\n"), "{}", stderr(&output));
+}
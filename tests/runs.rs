@@ -0,0 +1,116 @@
+// Whole runs against simulated replies
+#![cfg(feature = "simulate")]
+
+mod common;
+
+use std::fs;
+
+use common::{ping, recording, stderr, stdout};
+
+const TARGET: &str = "192.0.2.7";
+
+#[test]
+fn require_decides_the_exit_status() {
+    let run = recording("require", &[5.0, -1.0, -1.0]);
+    let path = run.to_str().unwrap();
+    let failed = ping(&["--simulate", path, "-c", "3", "-W", "50ms", "--require", "2/3", TARGET]);
+    let passed = ping(&["--simulate", path, "-c", "3", "-W", "50ms", "--require", "1/3", TARGET]);
+    fs::remove_file(&run).unwrap();
+
+    assert_eq!(failed.status.code(), Some(1), "{}", stderr(&failed));
+    assert!(stdout(&failed).ends_with("verdict: FAIL (1/3 < 2/3)\n"));
+    assert_eq!(passed.status.code(), Some(0), "{}", stderr(&passed));
+    assert!(stdout(&passed).ends_with("verdict: PASS (1/3 ≥ 1/3)\n"));
+}
+
+#[test]
+fn oneline_sums_up_a_partial_run() {
+    let run = recording("oneline", &[12.0, -1.0, 13.0]);
+    let output = ping(&["--simulate", run.to_str().unwrap(), "-c", "3", "-W", "50ms", "--oneline", TARGET]);
+    fs::remove_file(&run).unwrap();
+
+    // The rtts are measured, so only roughly what was recorded
+    let line = stdout(&output);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(line.starts_with("192.0.2.7: 2/3 1"), "{}", line);
+    assert!(line.ends_with("ms\n"), "{}", line);
+    assert_eq!(line.matches('.').count(), 4, "{}", line);
+}
+
+#[test]
+fn self_stats_go_to_stderr() {
+    let output = ping(&["--simulate", "clean", "-c", "1", "--self-stats", TARGET]);
+    let stderr = stderr(&output);
+
+    assert_eq!(output.status.code(), Some(0));
+    let usage = stderr.lines().find(|l| l.starts_with("Self: ")).unwrap_or_else(|| panic!("{}", stderr));
+    assert!(usage.ends_with(" MiB peak memory"), "{}", usage);
+    assert!(!stdout(&output).contains("Self: "));
+}
+
+#[test]
+fn decimal_comma_only_changes_human_output() {
+    let result = std::env::temp_dir().join(format!("ping-first-result-{}", std::process::id()));
+    let output = ping(&["--simulate", "clean", "-c", "2", "--decimal-comma", "--first-result-file", result.to_str().unwrap(), TARGET]);
+    let json = fs::read_to_string(&result).unwrap();
+    fs::remove_file(&result).unwrap();
+
+    let stdout = stdout(&output);
+    assert!(stdout.contains("0/2 lost (0,00%)"), "{}", stdout);
+    assert!(stdout.contains("0,00% packet loss"), "{}", stdout);
+    assert!(!stdout.lines().any(|l| l.starts_with("round-trip") && l.contains('.')), "{}", stdout);
+    // The JSON is for programs, so it keeps the dot
+    let sent_at = json.split(r#""sent_at":"#).nth(1).and_then(|rest| rest.split(',').next()).unwrap();
+    assert!(sent_at.parse::<f64>().is_ok() && sent_at.contains('.'), "{}", json);
+}
+
+#[test]
+fn rtt_expected_sets_the_timeout() {
+    let derived = ping(&["--simulate", "clean", "-c", "1", "--rtt-expected", "600", TARGET]);
+    assert!(
+        stderr(&derived).contains("Expecting rtts around 600ms, with a timeout of 1.8s and 3.6s more for late replies."),
+        "{}",
+        stderr(&derived),
+    );
+    let grace = ping(&["--simulate", "clean", "-c", "1", "--rtt-expected", "600", "--late-grace", "500ms", TARGET]);
+    assert!(stderr(&grace).contains("with a timeout of 1.8s and 0.5s more for late replies."), "{}", stderr(&grace));
+
+    // A shorter explicit timeout wins, but is warned about
+    let explicit = ping(&["--simulate", "clean", "-c", "1", "--rtt-expected", "600", "-W", "500ms", TARGET]);
+    assert!(stderr(&explicit).contains("Warning: The timeout (0.5s) is shorter than the expected rtt (600ms)"), "{}", stderr(&explicit));
+}
+
+#[test]
+fn late_replies_count_within_the_grace() {
+    let run = recording("late", &[150.0, 5.0]);
+    let path = run.to_str().unwrap();
+    let late = ping(&["--simulate", path, "-c", "2", "-W", "100ms", "--late-grace", "1s", TARGET]);
+    let lost = ping(&["--simulate", path, "-c", "2", "-W", "100ms", TARGET]);
+    fs::remove_file(&run).unwrap();
+
+    assert_eq!(late.status.code(), Some(0), "{}", stderr(&late));
+    assert!(stdout(&late).contains("Late reply for icmp_seq 0:"), "{}", stdout(&late));
+    assert!(stdout(&late).contains("2 packets transmitted, 2 received,"), "{}", stdout(&late));
+    assert!(!stdout(&lost).contains("Late reply"), "{}", stdout(&lost));
+    assert!(stdout(&lost).contains("2 packets transmitted, 1 received,"), "{}", stdout(&lost));
+}
+
+#[test]
+fn rtt_only_keeps_stdout_for_the_rtts() {
+    let run = recording("rtt-only", &[5.0, -1.0, 5.0]);
+    let path = run.to_str().unwrap();
+    let default = ping(&["--simulate", path, "-c", "3", "-W", "50ms", "--rtt-only", TARGET]);
+    let custom = ping(&["--simulate", path, "-c", "3", "-W", "50ms", "--rtt-only", "--loss-value", "NaN", TARGET]);
+    let skipped = ping(&["--simulate", path, "-c", "3", "-W", "50ms", "--rtt-only", "--skip-loss", TARGET]);
+    fs::remove_file(&run).unwrap();
+
+    let lines = |output: &std::process::Output| stdout(output).lines()
+        .map(|l| if l.parse::<f64>().map(|ms| ms >= 5.0).unwrap_or(false) && l.contains('.') { "rtt" } else { l }.to_owned())
+        .collect::<Vec<_>>();
+    assert_eq!(lines(&default), vec!["rtt", "-1", "rtt"]);
+    assert_eq!(lines(&custom), vec!["rtt", "NaN", "rtt"]);
+    assert_eq!(lines(&skipped), vec!["rtt", "rtt"]);
+
+    // The banner and the summary go to stderr
+    assert!(stderr(&default).contains("3 packets transmitted, 2 received"));
+}
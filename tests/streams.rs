@@ -0,0 +1,61 @@
+// Which of stdout and stderr each kind of output goes to
+#![cfg(feature = "simulate")]
+
+mod common;
+
+use common::{ping, stderr, stdout};
+
+// A documentation address, so nothing is mistaken for a local one
+const TARGET: &str = "192.0.2.7";
+
+#[test]
+fn probe_lines_and_reports_go_to_stdout() {
+    let output = ping(&["--simulate", "clean", "-c", "2", "--loss-map", TARGET]);
+    let (stdout, stderr) = (stdout(&output), stderr(&output));
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout.lines().filter(|l| l.starts_with("Response received")).count(), 2);
+    assert!(stdout.contains("Loss map"));
+    assert!(stdout.contains("--- 192.0.2.7 ping statistics ---"));
+    assert!(stdout.contains("2 packets transmitted, 2 received"));
+
+    assert_eq!(stderr, "Simulating pings to 192.0.2.7...\n");
+}
+
+#[test]
+fn warnings_go_to_stderr() {
+    // Warns that the hook failed, since its command doesn't exist
+    let output = ping(&["--simulate", "lossy:100%", "-c", "3", "-W", "1ms", "--on-down", "/nonexistent/hook", TARGET]);
+    let (stdout, stderr) = (stdout(&output), stderr(&output));
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr.contains("Warning: The 'on_down' hook failed"), "{}", stderr);
+    assert!(!stdout.contains("Warning"));
+}
+
+#[test]
+fn oneline_prints_nothing_else() {
+    let output = ping(&["--simulate", "clean", "-c", "3", "--oneline", TARGET]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output).lines().count(), 1);
+    assert_eq!(stderr(&output), "");
+}
+
+#[test]
+fn oneline_silences_warnings_too() {
+    let output = ping(&["--simulate", "lossy:100%", "-c", "3", "-W", "1ms", "--oneline", "--on-down", "/nonexistent/hook", TARGET]);
+
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stdout(&output), "192.0.2.7: DOWN 0/3\n");
+    assert_eq!(stderr(&output), "");
+}
+
+#[test]
+fn errors_go_to_stderr() {
+    let output = ping(&["--simulate", "clean", "-c", "2", "--ttl", "lots", TARGET]);
+
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(stdout(&output), "");
+    assert!(stderr(&output).starts_with("Error: "));
+}
@@ -0,0 +1,76 @@
+// Exit statuses and messages for flags that can't work, checked before any
+// packet is sent, so these need neither root nor a network
+
+mod common;
+
+use common::{ping, stderr, stdout};
+
+const EXIT_USAGE: i32 = 2;
+
+// Checks that `args` are rejected with a message containing `message`
+fn rejected(args: &[&str], message: &str) {
+    let output = ping(args);
+    assert_eq!(output.status.code(), Some(EXIT_USAGE), "{:?}", args);
+    assert_eq!(stdout(&output), "", "{:?}", args);
+    assert!(stderr(&output).contains(message), "{:?}: {}", args, stderr(&output));
+}
+
+#[test]
+fn require_must_match_the_count() {
+    rejected(&["-c", "5", "--require", "8/10", "192.0.2.7"], "The 'require' flag is out of 10 probes, but 5 will be sent.");
+    rejected(&["-c", "10", "--require", "11/10", "192.0.2.7"], "must look like N/M");
+    rejected(&["--require", "8/10", "192.0.2.7"], "'--require' can only be used with '--count' or '--max-seq'");
+}
+
+#[test]
+fn the_late_grace_must_be_a_duration() {
+    rejected(&["--late-grace", "soon", "192.0.2.7"], "The value for the 'late_grace' flag must be a duration, like 2, 1.5s, or 500ms.");
+    rejected(&["--late-grace", "1e30", "192.0.2.7"], "The value for the 'late_grace' flag must be a duration");
+}
+
+#[test]
+fn literal_addresses_must_match_the_family() {
+    let output = ping(&["-6", "-c", "1", "192.0.2.7"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(stderr(&output), "Error: '-6' was given, but '192.0.2.7' is an IPv4 address.\n");
+
+    let output = ping(&["-4", "-c", "1", "2001:db8::7"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(stderr(&output), "Error: '-4' was given, but '2001:db8::7' is an IPv6 address.\n");
+}
+
+#[test]
+fn huge_sequence_ranges_are_counted_without_overflowing() {
+    rejected(
+        &["--max-seq", "18446744073709551615", "-c", "5", "192.0.2.7"],
+        "The sequence numbers 0 to 18446744073709551615 cover more than 18446744073709551615 probes, but the 'packet_count' flag asks for 5.",
+    );
+    // Not even the largest count there is
+    rejected(
+        &["--max-seq", "18446744073709551615", "-c", "18446744073709551615", "192.0.2.7"],
+        "cover more than 18446744073709551615 probes, but the 'packet_count' flag asks for 18446744073709551615.",
+    );
+    rejected(
+        &["--max-seq", "18446744073709551615", "--require", "1/18446744073709551615", "192.0.2.7"],
+        "The 'require' flag is out of 18446744073709551615 probes, but more than 18446744073709551615 will be sent.",
+    );
+    // One short of the whole range fits
+    rejected(
+        &["--seq-start", "1", "--max-seq", "18446744073709551615", "-c", "5", "192.0.2.7"],
+        "The sequence numbers 1 to 18446744073709551615 cover 18446744073709551615 probes,",
+    );
+    rejected(&["-c", "18446744073709551616", "192.0.2.7"], "The value for the 'packet_count' flag");
+}
+
+#[test]
+fn accepted_sources_must_be_addresses_or_ranges() {
+    for value in &["gw", "192.0.2.0/33", "192.0.2.1,", "2001:db8::/129"] {
+        rejected(&["--accept-from", value, "-c", "1", "192.0.2.7"], "The value for the 'accept_from' flag must be a comma-separated list");
+    }
+}
+
+#[test]
+fn the_first_result_descriptor_must_be_open() {
+    rejected(&["--first-result-fd", "2", "-c", "1", "192.0.2.7"], "must be a file descriptor above 2");
+    rejected(&["--first-result-fd", "987", "-c", "1", "192.0.2.7"], "The file descriptor given to the 'first_result_fd' flag can't be used: Bad file descriptor");
+}